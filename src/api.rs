@@ -0,0 +1,1466 @@
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use failure::Error;
+
+use gpio::IoSample;
+
+pub const START_DELIMITER: u8 = 0x7E;
+pub const ESCAPE: u8 = 0x7D;
+pub const XON: u8 = 0x11;
+pub const XOFF: u8 = 0x13;
+pub const MAX_FRAME_LENGTH: usize = 0x1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiMode {
+    Unescaped,
+    Escaped,
+}
+
+#[derive(Debug, Fail)]
+pub enum ApiError {
+    #[fail(display = "Frame length {} is out of range.", _0)]
+    InvalidLength(usize),
+    #[fail(display = "Checksum does not match frame.")]
+    InvalidChecksum,
+    #[fail(display = "Not enough data to form frame type {:#04x}.", _0)]
+    NotEnoughData(u8),
+    #[fail(display = "Frame was cut off by a start delimiter.")]
+    Truncated,
+    #[fail(display = "Radio is not in API mode (ATAP = {}).", _0)]
+    UnsupportedMode(u8),
+    #[fail(display = "Operation requires API mode.")]
+    NotInApiMode,
+    #[fail(display = "Timed out waiting for response to frame {}.", _0)]
+    Timeout(u8),
+    #[fail(display = "Frame read timed out.")]
+    ReadTimeout,
+    #[fail(display = "All frame IDs are waiting on responses.")]
+    NoFreeFrameId,
+    #[fail(display = "Could not enter command mode.")]
+    CommandModeFailed,
+    #[fail(display = "Transmission failed with status {:?}.", _0)]
+    DeliveryFailed(DeliveryStatus),
+    #[fail(display = "Unexpected response frame type {:#04x}.", _0)]
+    UnexpectedFrame(u8),
+}
+
+#[derive(Debug, Fail)]
+pub enum CommandError {
+    #[fail(display = "AT command {} failed.", _0)]
+    Error(String),
+    #[fail(display = "Invalid AT command {}.", _0)]
+    InvalidCommand(String),
+    #[fail(display = "Invalid parameter for AT command {}.", _0)]
+    InvalidParameter(String),
+    #[fail(display = "Transmission of AT command {} failed.", _0)]
+    TxFailure(String),
+    #[fail(display = "AT command {} returned unknown status {:#04x}.", _0, _1)]
+    UnknownStatus(String, u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandStatus {
+    Ok,
+    Error,
+    InvalidCommand,
+    InvalidParameter,
+    TxFailure,
+    Unknown(u8),
+}
+
+impl CommandStatus {
+    pub fn from_value(value: u8) -> CommandStatus {
+        match value {
+            0 => CommandStatus::Ok,
+            1 => CommandStatus::Error,
+            2 => CommandStatus::InvalidCommand,
+            3 => CommandStatus::InvalidParameter,
+            4 => CommandStatus::TxFailure,
+            value => CommandStatus::Unknown(value),
+        }
+    }
+
+    pub fn value(&self) -> u8 {
+        match *self {
+            CommandStatus::Ok => 0,
+            CommandStatus::Error => 1,
+            CommandStatus::InvalidCommand => 2,
+            CommandStatus::InvalidParameter => 3,
+            CommandStatus::TxFailure => 4,
+            CommandStatus::Unknown(value) => value,
+        }
+    }
+
+    pub fn check(&self, command: &str) -> Result<(), Error> {
+        let command = command.to_string();
+
+        match *self {
+            CommandStatus::Ok => Ok(()),
+            CommandStatus::Error => Err(CommandError::Error(command).into()),
+            CommandStatus::InvalidCommand => Err(CommandError::InvalidCommand(command).into()),
+            CommandStatus::InvalidParameter => Err(CommandError::InvalidParameter(command).into()),
+            CommandStatus::TxFailure => Err(CommandError::TxFailure(command).into()),
+            CommandStatus::Unknown(value) => Err(CommandError::UnknownStatus(command, value).into()),
+        }
+    }
+}
+
+pub const BROADCAST_64: u64 = 0xFFFF;
+pub const UNKNOWN_64: u64 = 0xFFFF_FFFF_FFFF_FFFF;
+pub const UNKNOWN_16: u16 = 0xFFFE;
+
+// ZigBee and DigiMesh radios share one frame set; 802.15.4 (Series 1) radios
+// use the older addressing frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RadioFamily {
+    Series1,
+    Zigbee,
+}
+
+impl RadioFamily {
+    pub fn from_hardware_version(version: u16) -> RadioFamily {
+        match version >> 8 {
+            0x17 | 0x18 => RadioFamily::Series1,
+            _ => RadioFamily::Zigbee,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransmitOptions {
+    // Maximum hops for broadcasts; 0 uses the network maximum (ATNH).
+    pub broadcast_radius: u8,
+    pub disable_retries: bool,
+    pub encrypt: bool,
+    pub extended_timeout: bool,
+}
+
+impl Default for TransmitOptions {
+    fn default() -> TransmitOptions {
+        TransmitOptions::new()
+    }
+}
+
+impl TransmitOptions {
+    pub fn new() -> TransmitOptions {
+        TransmitOptions {
+            broadcast_radius: 0,
+            disable_retries: false,
+            encrypt: false,
+            extended_timeout: false,
+        }
+    }
+
+    pub fn from_bits(broadcast_radius: u8, bits: u8) -> TransmitOptions {
+        TransmitOptions {
+            broadcast_radius,
+            disable_retries: bits & 0x01 != 0,
+            encrypt: bits & 0x20 != 0,
+            extended_timeout: bits & 0x40 != 0,
+        }
+    }
+
+    pub fn bits(&self) -> u8 {
+        let mut bits = 0;
+
+        if self.disable_retries {
+            bits |= 0x01;
+        }
+
+        if self.encrypt {
+            bits |= 0x20;
+        }
+
+        if self.extended_timeout {
+            bits |= 0x40;
+        }
+
+        bits
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    Success,
+    MacAckFailure,
+    CcaFailure,
+    Purged,
+    InvalidDestinationEndpoint,
+    NetworkAckFailure,
+    NotJoined,
+    SelfAddressed,
+    AddressNotFound,
+    RouteNotFound,
+    BroadcastRelayNotHeard,
+    InvalidBindingIndex,
+    ResourceError,
+    PayloadTooLarge,
+    IndirectMessageUnrequested,
+    Unknown(u8),
+}
+
+impl DeliveryStatus {
+    pub fn from_value(value: u8) -> DeliveryStatus {
+        match value {
+            0x00 => DeliveryStatus::Success,
+            0x01 => DeliveryStatus::MacAckFailure,
+            0x02 => DeliveryStatus::CcaFailure,
+            0x03 => DeliveryStatus::Purged,
+            0x15 => DeliveryStatus::InvalidDestinationEndpoint,
+            0x21 => DeliveryStatus::NetworkAckFailure,
+            0x22 => DeliveryStatus::NotJoined,
+            0x23 => DeliveryStatus::SelfAddressed,
+            0x24 => DeliveryStatus::AddressNotFound,
+            0x25 => DeliveryStatus::RouteNotFound,
+            0x26 => DeliveryStatus::BroadcastRelayNotHeard,
+            0x2B => DeliveryStatus::InvalidBindingIndex,
+            0x2C => DeliveryStatus::ResourceError,
+            0x74 => DeliveryStatus::PayloadTooLarge,
+            0x75 => DeliveryStatus::IndirectMessageUnrequested,
+            value => DeliveryStatus::Unknown(value),
+        }
+    }
+
+    pub fn value(&self) -> u8 {
+        match *self {
+            DeliveryStatus::Success => 0x00,
+            DeliveryStatus::MacAckFailure => 0x01,
+            DeliveryStatus::CcaFailure => 0x02,
+            DeliveryStatus::Purged => 0x03,
+            DeliveryStatus::InvalidDestinationEndpoint => 0x15,
+            DeliveryStatus::NetworkAckFailure => 0x21,
+            DeliveryStatus::NotJoined => 0x22,
+            DeliveryStatus::SelfAddressed => 0x23,
+            DeliveryStatus::AddressNotFound => 0x24,
+            DeliveryStatus::RouteNotFound => 0x25,
+            DeliveryStatus::BroadcastRelayNotHeard => 0x26,
+            DeliveryStatus::InvalidBindingIndex => 0x2B,
+            DeliveryStatus::ResourceError => 0x2C,
+            DeliveryStatus::PayloadTooLarge => 0x74,
+            DeliveryStatus::IndirectMessageUnrequested => 0x75,
+            DeliveryStatus::Unknown(value) => value,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiscoveryStatus {
+    NoOverhead,
+    Address,
+    Route,
+    AddressAndRoute,
+    ExtendedTimeout,
+    Unknown(u8),
+}
+
+impl DiscoveryStatus {
+    pub fn from_value(value: u8) -> DiscoveryStatus {
+        match value {
+            0x00 => DiscoveryStatus::NoOverhead,
+            0x01 => DiscoveryStatus::Address,
+            0x02 => DiscoveryStatus::Route,
+            0x03 => DiscoveryStatus::AddressAndRoute,
+            0x40 => DiscoveryStatus::ExtendedTimeout,
+            value => DiscoveryStatus::Unknown(value),
+        }
+    }
+
+    pub fn value(&self) -> u8 {
+        match *self {
+            DiscoveryStatus::NoOverhead => 0x00,
+            DiscoveryStatus::Address => 0x01,
+            DiscoveryStatus::Route => 0x02,
+            DiscoveryStatus::AddressAndRoute => 0x03,
+            DiscoveryStatus::ExtendedTimeout => 0x40,
+            DiscoveryStatus::Unknown(value) => value,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransmitResult {
+    pub dest16: u16,
+    pub retries: u8,
+    pub delivery: DeliveryStatus,
+    pub discovery: DiscoveryStatus,
+}
+
+impl TransmitResult {
+    pub fn delivered(&self) -> bool {
+        self.delivery == DeliveryStatus::Success
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReceiveOptions {
+    pub acknowledged: bool,
+    pub broadcast: bool,
+    pub encrypted: bool,
+    pub from_end_device: bool,
+}
+
+impl ReceiveOptions {
+    pub fn from_bits(bits: u8) -> ReceiveOptions {
+        ReceiveOptions {
+            acknowledged: bits & 0x01 != 0,
+            broadcast: bits & 0x02 != 0,
+            encrypted: bits & 0x20 != 0,
+            from_end_device: bits & 0x40 != 0,
+        }
+    }
+
+    pub fn bits(&self) -> u8 {
+        let mut bits = 0;
+
+        if self.acknowledged {
+            bits |= 0x01;
+        }
+
+        if self.broadcast {
+            bits |= 0x02;
+        }
+
+        if self.encrypted {
+            bits |= 0x20;
+        }
+
+        if self.from_end_device {
+            bits |= 0x40;
+        }
+
+        bits
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExplicitAddress {
+    pub source_endpoint: u8,
+    pub dest_endpoint: u8,
+    pub cluster: u16,
+    pub profile: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReceivedMessage {
+    pub src64: u64,
+    pub src16: u16,
+    pub options: ReceiveOptions,
+    // Only reported by 802.15.4 (Series 1) radios, in dBm.
+    pub rssi: Option<i16>,
+    // Only present for messages from Explicit Rx Indicator frames.
+    pub explicit: Option<ExplicitAddress>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IoSampleMessage {
+    pub src64: u64,
+    pub src16: u16,
+    pub options: ReceiveOptions,
+    // Only reported by 802.15.4 (Series 1) radios, in dBm.
+    pub rssi: Option<i16>,
+    // ZigBee radios always send a single sample per frame.
+    pub samples: Vec<IoSample>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModemStatus {
+    HardwareReset,
+    WatchdogReset,
+    JoinedNetwork,
+    Disassociated,
+    SynchronizationLost,
+    CoordinatorRealignment,
+    CoordinatorStarted,
+    NetworkKeyUpdated,
+    NetworkWokeUp,
+    NetworkWentToSleep,
+    VoltageExceeded,
+    ConfigurationChangedWhileJoining,
+    StackError(u8),
+    Unknown(u8),
+}
+
+impl ModemStatus {
+    pub fn from_value(value: u8) -> ModemStatus {
+        match value {
+            0x00 => ModemStatus::HardwareReset,
+            0x01 => ModemStatus::WatchdogReset,
+            0x02 => ModemStatus::JoinedNetwork,
+            0x03 => ModemStatus::Disassociated,
+            0x04 => ModemStatus::SynchronizationLost,
+            0x05 => ModemStatus::CoordinatorRealignment,
+            0x06 => ModemStatus::CoordinatorStarted,
+            0x07 => ModemStatus::NetworkKeyUpdated,
+            0x0B => ModemStatus::NetworkWokeUp,
+            0x0C => ModemStatus::NetworkWentToSleep,
+            0x0D => ModemStatus::VoltageExceeded,
+            0x11 => ModemStatus::ConfigurationChangedWhileJoining,
+            value if value >= 0x80 => ModemStatus::StackError(value),
+            value => ModemStatus::Unknown(value),
+        }
+    }
+
+    pub fn value(&self) -> u8 {
+        match *self {
+            ModemStatus::HardwareReset => 0x00,
+            ModemStatus::WatchdogReset => 0x01,
+            ModemStatus::JoinedNetwork => 0x02,
+            ModemStatus::Disassociated => 0x03,
+            ModemStatus::SynchronizationLost => 0x04,
+            ModemStatus::CoordinatorRealignment => 0x05,
+            ModemStatus::CoordinatorStarted => 0x06,
+            ModemStatus::NetworkKeyUpdated => 0x07,
+            ModemStatus::NetworkWokeUp => 0x0B,
+            ModemStatus::NetworkWentToSleep => 0x0C,
+            ModemStatus::VoltageExceeded => 0x0D,
+            ModemStatus::ConfigurationChangedWhileJoining => 0x11,
+            ModemStatus::StackError(value) | ModemStatus::Unknown(value) => value,
+        }
+    }
+
+    // Whether the status reports joining or leaving a network, if it says
+    // anything about it at all.
+    pub fn associated(&self) -> Option<bool> {
+        match *self {
+            ModemStatus::JoinedNetwork | ModemStatus::CoordinatorStarted => Some(true),
+            ModemStatus::HardwareReset
+            | ModemStatus::WatchdogReset
+            | ModemStatus::Disassociated
+            | ModemStatus::SynchronizationLost => Some(false),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceType {
+    Coordinator,
+    Router,
+    EndDevice,
+    Unknown(u8),
+}
+
+impl DeviceType {
+    pub fn from_value(value: u8) -> DeviceType {
+        match value {
+            0 => DeviceType::Coordinator,
+            1 => DeviceType::Router,
+            2 => DeviceType::EndDevice,
+            value => DeviceType::Unknown(value),
+        }
+    }
+
+    pub fn value(&self) -> u8 {
+        match *self {
+            DeviceType::Coordinator => 0,
+            DeviceType::Router => 1,
+            DeviceType::EndDevice => 2,
+            DeviceType::Unknown(value) => value,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceEvent {
+    PushButton,
+    Joined,
+    PowerCycle,
+    Unknown(u8),
+}
+
+impl SourceEvent {
+    pub fn from_value(value: u8) -> SourceEvent {
+        match value {
+            1 => SourceEvent::PushButton,
+            2 => SourceEvent::Joined,
+            3 => SourceEvent::PowerCycle,
+            value => SourceEvent::Unknown(value),
+        }
+    }
+
+    pub fn value(&self) -> u8 {
+        match *self {
+            SourceEvent::PushButton => 1,
+            SourceEvent::Joined => 2,
+            SourceEvent::PowerCycle => 3,
+            SourceEvent::Unknown(value) => value,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeIdentification {
+    // Radio that relayed the indicator.
+    pub src64: u64,
+    pub src16: u16,
+    pub options: ReceiveOptions,
+    // Radio that was identified.
+    pub remote64: u64,
+    pub remote16: u16,
+    pub identifier: String,
+    pub parent16: u16,
+    pub device_type: DeviceType,
+    pub source_event: SourceEvent,
+    pub profile: u16,
+    pub manufacturer: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ApiFrame {
+    AtCommand {
+        frame_id: u8,
+        command: String,
+        parameter: Vec<u8>,
+    },
+    QueueParameterValue {
+        frame_id: u8,
+        command: String,
+        parameter: Vec<u8>,
+    },
+    AtCommandResponse {
+        frame_id: u8,
+        command: String,
+        status: CommandStatus,
+        data: Vec<u8>,
+    },
+    TransmitRequest {
+        frame_id: u8,
+        dest64: u64,
+        dest16: u16,
+        options: TransmitOptions,
+        data: Vec<u8>,
+    },
+    TransmitStatus {
+        frame_id: u8,
+        result: TransmitResult,
+    },
+    ReceivePacket(ReceivedMessage),
+    ExplicitRxIndicator(ReceivedMessage),
+    RemoteAtCommand {
+        frame_id: u8,
+        dest64: u64,
+        dest16: u16,
+        apply_changes: bool,
+        command: String,
+        parameter: Vec<u8>,
+    },
+    RemoteAtCommandResponse {
+        frame_id: u8,
+        src64: u64,
+        src16: u16,
+        command: String,
+        status: CommandStatus,
+        data: Vec<u8>,
+    },
+    IoSampleIndicator(IoSampleMessage),
+    ModemStatus(ModemStatus),
+    NodeIdentification(NodeIdentification),
+    Transmit64 {
+        frame_id: u8,
+        dest64: u64,
+        options: TransmitOptions,
+        data: Vec<u8>,
+    },
+    Transmit16 {
+        frame_id: u8,
+        dest16: u16,
+        options: TransmitOptions,
+        data: Vec<u8>,
+    },
+    TxStatus {
+        frame_id: u8,
+        delivery: DeliveryStatus,
+    },
+    Receive64(ReceivedMessage),
+    Receive16(ReceivedMessage),
+    IoReceive64(IoSampleMessage),
+    IoReceive16(IoSampleMessage),
+    ExplicitTransmit {
+        frame_id: u8,
+        dest64: u64,
+        dest16: u16,
+        address: ExplicitAddress,
+        options: TransmitOptions,
+        data: Vec<u8>,
+    },
+    RouteRecord {
+        src64: u64,
+        src16: u16,
+        options: ReceiveOptions,
+        // Intermediate hops, closest to the source first.
+        hops: Vec<u16>,
+    },
+    CreateSourceRoute {
+        frame_id: u8,
+        dest64: u64,
+        dest16: u16,
+        // Intermediate hops, closest to the destination first.
+        hops: Vec<u16>,
+    },
+    Unknown {
+        frame_type: u8,
+        data: Vec<u8>,
+    },
+}
+
+impl ApiMode {
+    pub fn from_ap(value: u8) -> Result<ApiMode, Error> {
+        match value {
+            1 => Ok(ApiMode::Unescaped),
+            2 => Ok(ApiMode::Escaped),
+            _ => Err(ApiError::UnsupportedMode(value).into()),
+        }
+    }
+
+    pub fn ap(&self) -> u8 {
+        match *self {
+            ApiMode::Unescaped => 1,
+            ApiMode::Escaped => 2,
+        }
+    }
+}
+
+fn calculate_checksum(frame: &[u8]) -> u8 {
+    0xFF - frame.iter().fold(0u8, |sum, &elem| sum.wrapping_add(elem))
+}
+
+fn needs_escape(byte: u8) -> bool {
+    byte == START_DELIMITER || byte == ESCAPE || byte == XON || byte == XOFF
+}
+
+pub fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+
+    for &byte in data {
+        if needs_escape(byte) {
+            escaped.push(ESCAPE);
+            escaped.push(byte ^ 0x20);
+        } else {
+            escaped.push(byte);
+        }
+    }
+
+    escaped
+}
+
+// Packs a numeric AT parameter into the shortest big-endian byte string.
+pub fn parameter_bytes(value: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.write_u64::<BigEndian>(value).unwrap();
+
+    let skip = bytes.iter().take(7).take_while(|&&byte| byte == 0).count();
+    bytes.split_off(skip)
+}
+
+pub fn parameter_value(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |value, &byte| value << 8 | byte as u64)
+}
+
+fn command_name(raw: &[u8]) -> String {
+    String::from_utf8_lossy(raw).into_owned()
+}
+
+fn legacy_rssi(value: u8) -> Option<i16> {
+    Some(-(value as i16))
+}
+
+impl ApiFrame {
+    pub fn parse(frame_type: u8, data: &[u8]) -> Result<ApiFrame, Error> {
+        let frame = match frame_type {
+            0x08 | 0x09 => {
+                ensure!(data.len() >= 3, ApiError::NotEnoughData(frame_type));
+
+                let frame_id = data[0];
+                let command = command_name(&data[1..3]);
+                let parameter = data[3..].into();
+
+                if frame_type == 0x08 {
+                    ApiFrame::AtCommand { frame_id, command, parameter }
+                } else {
+                    ApiFrame::QueueParameterValue { frame_id, command, parameter }
+                }
+            }
+            0x88 => {
+                ensure!(data.len() >= 4, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::AtCommandResponse {
+                    frame_id: data[0],
+                    command: command_name(&data[1..3]),
+                    status: CommandStatus::from_value(data[3]),
+                    data: data[4..].into(),
+                }
+            }
+            0x10 => {
+                ensure!(data.len() >= 13, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::TransmitRequest {
+                    frame_id: data[0],
+                    dest64: BigEndian::read_u64(&data[1..]),
+                    dest16: BigEndian::read_u16(&data[9..]),
+                    options: TransmitOptions::from_bits(data[11], data[12]),
+                    data: data[13..].into(),
+                }
+            }
+            0x8B => {
+                ensure!(data.len() >= 6, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::TransmitStatus {
+                    frame_id: data[0],
+                    result: TransmitResult {
+                        dest16: BigEndian::read_u16(&data[1..]),
+                        retries: data[3],
+                        delivery: DeliveryStatus::from_value(data[4]),
+                        discovery: DiscoveryStatus::from_value(data[5]),
+                    },
+                }
+            }
+            0x90 => {
+                ensure!(data.len() >= 11, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::ReceivePacket(ReceivedMessage {
+                    src64: BigEndian::read_u64(data),
+                    src16: BigEndian::read_u16(&data[8..]),
+                    options: ReceiveOptions::from_bits(data[10]),
+                    rssi: None,
+                    explicit: None,
+                    data: data[11..].into(),
+                })
+            }
+            0x91 => {
+                ensure!(data.len() >= 17, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::ExplicitRxIndicator(ReceivedMessage {
+                    src64: BigEndian::read_u64(data),
+                    src16: BigEndian::read_u16(&data[8..]),
+                    options: ReceiveOptions::from_bits(data[16]),
+                    rssi: None,
+                    explicit: Some(ExplicitAddress {
+                        source_endpoint: data[10],
+                        dest_endpoint: data[11],
+                        cluster: BigEndian::read_u16(&data[12..]),
+                        profile: BigEndian::read_u16(&data[14..]),
+                    }),
+                    data: data[17..].into(),
+                })
+            }
+            0x17 => {
+                ensure!(data.len() >= 14, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::RemoteAtCommand {
+                    frame_id: data[0],
+                    dest64: BigEndian::read_u64(&data[1..]),
+                    dest16: BigEndian::read_u16(&data[9..]),
+                    apply_changes: data[11] & 0x02 != 0,
+                    command: command_name(&data[12..14]),
+                    parameter: data[14..].into(),
+                }
+            }
+            0x97 => {
+                ensure!(data.len() >= 14, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::RemoteAtCommandResponse {
+                    frame_id: data[0],
+                    src64: BigEndian::read_u64(&data[1..]),
+                    src16: BigEndian::read_u16(&data[9..]),
+                    command: command_name(&data[11..13]),
+                    status: CommandStatus::from_value(data[13]),
+                    data: data[14..].into(),
+                }
+            }
+            0x92 => {
+                ensure!(data.len() >= 11, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::IoSampleIndicator(IoSampleMessage {
+                    src64: BigEndian::read_u64(data),
+                    src16: BigEndian::read_u16(&data[8..]),
+                    options: ReceiveOptions::from_bits(data[10]),
+                    rssi: None,
+                    samples: vec![IoSample::from_bytes(&data[11..])?],
+                })
+            }
+            0x8A => {
+                ensure!(!data.is_empty(), ApiError::NotEnoughData(frame_type));
+                ApiFrame::ModemStatus(ModemStatus::from_value(data[0]))
+            }
+            0x95 => {
+                ensure!(data.len() >= 22, ApiError::NotEnoughData(frame_type));
+
+                let end = data[21..].iter()
+                    .position(|&byte| byte == 0)
+                    .ok_or(ApiError::NotEnoughData(frame_type))? + 21;
+
+                ensure!(data.len() >= end + 9, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::NodeIdentification(NodeIdentification {
+                    src64: BigEndian::read_u64(data),
+                    src16: BigEndian::read_u16(&data[8..]),
+                    options: ReceiveOptions::from_bits(data[10]),
+                    remote16: BigEndian::read_u16(&data[11..]),
+                    remote64: BigEndian::read_u64(&data[13..]),
+                    identifier: String::from_utf8_lossy(&data[21..end]).into_owned(),
+                    parent16: BigEndian::read_u16(&data[end + 1..]),
+                    device_type: DeviceType::from_value(data[end + 3]),
+                    source_event: SourceEvent::from_value(data[end + 4]),
+                    profile: BigEndian::read_u16(&data[end + 5..]),
+                    manufacturer: BigEndian::read_u16(&data[end + 7..]),
+                })
+            }
+            0x00 => {
+                ensure!(data.len() >= 10, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::Transmit64 {
+                    frame_id: data[0],
+                    dest64: BigEndian::read_u64(&data[1..]),
+                    options: TransmitOptions::from_bits(0, data[9]),
+                    data: data[10..].into(),
+                }
+            }
+            0x01 => {
+                ensure!(data.len() >= 4, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::Transmit16 {
+                    frame_id: data[0],
+                    dest16: BigEndian::read_u16(&data[1..]),
+                    options: TransmitOptions::from_bits(0, data[3]),
+                    data: data[4..].into(),
+                }
+            }
+            0x89 => {
+                ensure!(data.len() >= 2, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::TxStatus {
+                    frame_id: data[0],
+                    delivery: DeliveryStatus::from_value(data[1]),
+                }
+            }
+            0x80 => {
+                ensure!(data.len() >= 10, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::Receive64(ReceivedMessage {
+                    src64: BigEndian::read_u64(data),
+                    src16: UNKNOWN_16,
+                    options: ReceiveOptions::from_bits(data[9]),
+                    rssi: legacy_rssi(data[8]),
+                    explicit: None,
+                    data: data[10..].into(),
+                })
+            }
+            0x81 => {
+                ensure!(data.len() >= 4, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::Receive16(ReceivedMessage {
+                    src64: UNKNOWN_64,
+                    src16: BigEndian::read_u16(data),
+                    options: ReceiveOptions::from_bits(data[3]),
+                    rssi: legacy_rssi(data[2]),
+                    explicit: None,
+                    data: data[4..].into(),
+                })
+            }
+            0x82 => {
+                ensure!(data.len() >= 10, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::IoReceive64(IoSampleMessage {
+                    src64: BigEndian::read_u64(data),
+                    src16: UNKNOWN_16,
+                    options: ReceiveOptions::from_bits(data[9]),
+                    rssi: legacy_rssi(data[8]),
+                    samples: IoSample::from_legacy_bytes(&data[10..])?,
+                })
+            }
+            0x83 => {
+                ensure!(data.len() >= 4, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::IoReceive16(IoSampleMessage {
+                    src64: UNKNOWN_64,
+                    src16: BigEndian::read_u16(data),
+                    options: ReceiveOptions::from_bits(data[3]),
+                    rssi: legacy_rssi(data[2]),
+                    samples: IoSample::from_legacy_bytes(&data[4..])?,
+                })
+            }
+            0x11 => {
+                ensure!(data.len() >= 19, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::ExplicitTransmit {
+                    frame_id: data[0],
+                    dest64: BigEndian::read_u64(&data[1..]),
+                    dest16: BigEndian::read_u16(&data[9..]),
+                    address: ExplicitAddress {
+                        source_endpoint: data[11],
+                        dest_endpoint: data[12],
+                        cluster: BigEndian::read_u16(&data[13..]),
+                        profile: BigEndian::read_u16(&data[15..]),
+                    },
+                    options: TransmitOptions::from_bits(data[17], data[18]),
+                    data: data[19..].into(),
+                }
+            }
+            0xA1 => {
+                ensure!(data.len() >= 12, ApiError::NotEnoughData(frame_type));
+                ensure!(data.len() >= 12 + data[11] as usize * 2, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::RouteRecord {
+                    src64: BigEndian::read_u64(data),
+                    src16: BigEndian::read_u16(&data[8..]),
+                    options: ReceiveOptions::from_bits(data[10]),
+                    hops: data[12..12 + data[11] as usize * 2]
+                        .chunks(2)
+                        .map(BigEndian::read_u16)
+                        .collect(),
+                }
+            }
+            0x21 => {
+                ensure!(data.len() >= 13, ApiError::NotEnoughData(frame_type));
+                ensure!(data.len() >= 13 + data[12] as usize * 2, ApiError::NotEnoughData(frame_type));
+
+                ApiFrame::CreateSourceRoute {
+                    frame_id: data[0],
+                    dest64: BigEndian::read_u64(&data[1..]),
+                    dest16: BigEndian::read_u16(&data[9..]),
+                    hops: data[13..13 + data[12] as usize * 2]
+                        .chunks(2)
+                        .map(BigEndian::read_u16)
+                        .collect(),
+                }
+            }
+            _ => ApiFrame::Unknown {
+                frame_type,
+                data: data.into(),
+            },
+        };
+
+        Ok(frame)
+    }
+
+    pub fn frame_type(&self) -> u8 {
+        match *self {
+            ApiFrame::AtCommand { .. } => 0x08,
+            ApiFrame::QueueParameterValue { .. } => 0x09,
+            ApiFrame::AtCommandResponse { .. } => 0x88,
+            ApiFrame::TransmitRequest { .. } => 0x10,
+            ApiFrame::TransmitStatus { .. } => 0x8B,
+            ApiFrame::ReceivePacket(_) => 0x90,
+            ApiFrame::ExplicitRxIndicator(_) => 0x91,
+            ApiFrame::RemoteAtCommand { .. } => 0x17,
+            ApiFrame::RemoteAtCommandResponse { .. } => 0x97,
+            ApiFrame::IoSampleIndicator(_) => 0x92,
+            ApiFrame::ModemStatus(_) => 0x8A,
+            ApiFrame::NodeIdentification(_) => 0x95,
+            ApiFrame::Transmit64 { .. } => 0x00,
+            ApiFrame::Transmit16 { .. } => 0x01,
+            ApiFrame::TxStatus { .. } => 0x89,
+            ApiFrame::Receive64(_) => 0x80,
+            ApiFrame::Receive16(_) => 0x81,
+            ApiFrame::IoReceive64(_) => 0x82,
+            ApiFrame::IoReceive16(_) => 0x83,
+            ApiFrame::ExplicitTransmit { .. } => 0x11,
+            ApiFrame::RouteRecord { .. } => 0xA1,
+            ApiFrame::CreateSourceRoute { .. } => 0x21,
+            ApiFrame::Unknown { frame_type, .. } => frame_type,
+        }
+    }
+
+    // Frame ID used to correlate requests with their responses, for frame types
+    // that carry one.
+    pub fn frame_id(&self) -> Option<u8> {
+        match *self {
+            ApiFrame::AtCommand { frame_id, .. }
+            | ApiFrame::QueueParameterValue { frame_id, .. }
+            | ApiFrame::AtCommandResponse { frame_id, .. }
+            | ApiFrame::TransmitRequest { frame_id, .. }
+            | ApiFrame::TransmitStatus { frame_id, .. }
+            | ApiFrame::RemoteAtCommand { frame_id, .. }
+            | ApiFrame::RemoteAtCommandResponse { frame_id, .. }
+            | ApiFrame::Transmit64 { frame_id, .. }
+            | ApiFrame::Transmit16 { frame_id, .. }
+            | ApiFrame::TxStatus { frame_id, .. }
+            | ApiFrame::ExplicitTransmit { frame_id, .. }
+            | ApiFrame::CreateSourceRoute { frame_id, .. } => Some(frame_id),
+            _ => None,
+        }
+    }
+
+    pub fn message(&self) -> Option<&ReceivedMessage> {
+        match *self {
+            ApiFrame::ReceivePacket(ref message)
+            | ApiFrame::ExplicitRxIndicator(ref message)
+            | ApiFrame::Receive64(ref message)
+            | ApiFrame::Receive16(ref message) => Some(message),
+            _ => None,
+        }
+    }
+
+    pub fn io_sample(&self) -> Option<&IoSampleMessage> {
+        match *self {
+            ApiFrame::IoSampleIndicator(ref message)
+            | ApiFrame::IoReceive64(ref message)
+            | ApiFrame::IoReceive16(ref message) => Some(message),
+            _ => None,
+        }
+    }
+
+    pub fn data(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        match *self {
+            ApiFrame::AtCommand { frame_id, ref command, ref parameter }
+            | ApiFrame::QueueParameterValue { frame_id, ref command, ref parameter } => {
+                bytes.write_u8(frame_id).unwrap();
+                bytes.extend_from_slice(command.as_bytes());
+                bytes.extend_from_slice(parameter);
+            }
+            ApiFrame::AtCommandResponse { frame_id, ref command, status, ref data } => {
+                bytes.write_u8(frame_id).unwrap();
+                bytes.extend_from_slice(command.as_bytes());
+                bytes.write_u8(status.value()).unwrap();
+                bytes.extend_from_slice(data);
+            }
+            ApiFrame::TransmitRequest { frame_id, dest64, dest16, options, ref data } => {
+                bytes.write_u8(frame_id).unwrap();
+                bytes.write_u64::<BigEndian>(dest64).unwrap();
+                bytes.write_u16::<BigEndian>(dest16).unwrap();
+                bytes.write_u8(options.broadcast_radius).unwrap();
+                bytes.write_u8(options.bits()).unwrap();
+                bytes.extend_from_slice(data);
+            }
+            ApiFrame::TransmitStatus { frame_id, result } => {
+                bytes.write_u8(frame_id).unwrap();
+                bytes.write_u16::<BigEndian>(result.dest16).unwrap();
+                bytes.write_u8(result.retries).unwrap();
+                bytes.write_u8(result.delivery.value()).unwrap();
+                bytes.write_u8(result.discovery.value()).unwrap();
+            }
+            ApiFrame::ReceivePacket(ref message) | ApiFrame::ExplicitRxIndicator(ref message) => {
+                bytes.write_u64::<BigEndian>(message.src64).unwrap();
+                bytes.write_u16::<BigEndian>(message.src16).unwrap();
+
+                if let Some(explicit) = message.explicit {
+                    bytes.write_u8(explicit.source_endpoint).unwrap();
+                    bytes.write_u8(explicit.dest_endpoint).unwrap();
+                    bytes.write_u16::<BigEndian>(explicit.cluster).unwrap();
+                    bytes.write_u16::<BigEndian>(explicit.profile).unwrap();
+                }
+
+                bytes.write_u8(message.options.bits()).unwrap();
+                bytes.extend_from_slice(&message.data);
+            }
+            ApiFrame::RemoteAtCommand { frame_id, dest64, dest16, apply_changes, ref command, ref parameter } => {
+                bytes.write_u8(frame_id).unwrap();
+                bytes.write_u64::<BigEndian>(dest64).unwrap();
+                bytes.write_u16::<BigEndian>(dest16).unwrap();
+                bytes.write_u8(if apply_changes { 0x02 } else { 0x00 }).unwrap();
+                bytes.extend_from_slice(command.as_bytes());
+                bytes.extend_from_slice(parameter);
+            }
+            ApiFrame::RemoteAtCommandResponse { frame_id, src64, src16, ref command, status, ref data } => {
+                bytes.write_u8(frame_id).unwrap();
+                bytes.write_u64::<BigEndian>(src64).unwrap();
+                bytes.write_u16::<BigEndian>(src16).unwrap();
+                bytes.extend_from_slice(command.as_bytes());
+                bytes.write_u8(status.value()).unwrap();
+                bytes.extend_from_slice(data);
+            }
+            ApiFrame::IoSampleIndicator(ref message) => {
+                bytes.write_u64::<BigEndian>(message.src64).unwrap();
+                bytes.write_u16::<BigEndian>(message.src16).unwrap();
+                bytes.write_u8(message.options.bits()).unwrap();
+                if let Some(sample) = message.samples.first() {
+                    bytes.extend_from_slice(&sample.as_bytes());
+                }
+            }
+            ApiFrame::ModemStatus(status) => {
+                bytes.write_u8(status.value()).unwrap();
+            }
+            ApiFrame::NodeIdentification(ref node) => {
+                bytes.write_u64::<BigEndian>(node.src64).unwrap();
+                bytes.write_u16::<BigEndian>(node.src16).unwrap();
+                bytes.write_u8(node.options.bits()).unwrap();
+                bytes.write_u16::<BigEndian>(node.remote16).unwrap();
+                bytes.write_u64::<BigEndian>(node.remote64).unwrap();
+                bytes.extend_from_slice(node.identifier.as_bytes());
+                bytes.write_u8(0).unwrap();
+                bytes.write_u16::<BigEndian>(node.parent16).unwrap();
+                bytes.write_u8(node.device_type.value()).unwrap();
+                bytes.write_u8(node.source_event.value()).unwrap();
+                bytes.write_u16::<BigEndian>(node.profile).unwrap();
+                bytes.write_u16::<BigEndian>(node.manufacturer).unwrap();
+            }
+            ApiFrame::Transmit64 { frame_id, dest64, options, ref data } => {
+                bytes.write_u8(frame_id).unwrap();
+                bytes.write_u64::<BigEndian>(dest64).unwrap();
+                bytes.write_u8(options.bits()).unwrap();
+                bytes.extend_from_slice(data);
+            }
+            ApiFrame::Transmit16 { frame_id, dest16, options, ref data } => {
+                bytes.write_u8(frame_id).unwrap();
+                bytes.write_u16::<BigEndian>(dest16).unwrap();
+                bytes.write_u8(options.bits()).unwrap();
+                bytes.extend_from_slice(data);
+            }
+            ApiFrame::TxStatus { frame_id, delivery } => {
+                bytes.write_u8(frame_id).unwrap();
+                bytes.write_u8(delivery.value()).unwrap();
+            }
+            ApiFrame::Receive64(ref message) | ApiFrame::Receive16(ref message) => {
+                if self.frame_type() == 0x80 {
+                    bytes.write_u64::<BigEndian>(message.src64).unwrap();
+                } else {
+                    bytes.write_u16::<BigEndian>(message.src16).unwrap();
+                }
+
+                bytes.write_u8(message.rssi.map_or(0, |rssi| -rssi as u8)).unwrap();
+                bytes.write_u8(message.options.bits()).unwrap();
+                bytes.extend_from_slice(&message.data);
+            }
+            ApiFrame::IoReceive64(ref message) | ApiFrame::IoReceive16(ref message) => {
+                if self.frame_type() == 0x82 {
+                    bytes.write_u64::<BigEndian>(message.src64).unwrap();
+                } else {
+                    bytes.write_u16::<BigEndian>(message.src16).unwrap();
+                }
+
+                bytes.write_u8(message.rssi.map_or(0, |rssi| -rssi as u8)).unwrap();
+                bytes.write_u8(message.options.bits()).unwrap();
+                bytes.extend_from_slice(&IoSample::legacy_bytes(&message.samples));
+            }
+            ApiFrame::ExplicitTransmit { frame_id, dest64, dest16, address, options, ref data } => {
+                bytes.write_u8(frame_id).unwrap();
+                bytes.write_u64::<BigEndian>(dest64).unwrap();
+                bytes.write_u16::<BigEndian>(dest16).unwrap();
+                bytes.write_u8(address.source_endpoint).unwrap();
+                bytes.write_u8(address.dest_endpoint).unwrap();
+                bytes.write_u16::<BigEndian>(address.cluster).unwrap();
+                bytes.write_u16::<BigEndian>(address.profile).unwrap();
+                bytes.write_u8(options.broadcast_radius).unwrap();
+                bytes.write_u8(options.bits()).unwrap();
+                bytes.extend_from_slice(data);
+            }
+            ApiFrame::RouteRecord { src64, src16, options, ref hops } => {
+                bytes.write_u64::<BigEndian>(src64).unwrap();
+                bytes.write_u16::<BigEndian>(src16).unwrap();
+                bytes.write_u8(options.bits()).unwrap();
+                bytes.write_u8(hops.len() as u8).unwrap();
+
+                for &hop in hops {
+                    bytes.write_u16::<BigEndian>(hop).unwrap();
+                }
+            }
+            ApiFrame::CreateSourceRoute { frame_id, dest64, dest16, ref hops } => {
+                bytes.write_u8(frame_id).unwrap();
+                bytes.write_u64::<BigEndian>(dest64).unwrap();
+                bytes.write_u16::<BigEndian>(dest16).unwrap();
+                bytes.write_u8(0).unwrap();
+                bytes.write_u8(hops.len() as u8).unwrap();
+
+                for &hop in hops {
+                    bytes.write_u16::<BigEndian>(hop).unwrap();
+                }
+            }
+            ApiFrame::Unknown { ref data, .. } => {
+                bytes.extend_from_slice(data);
+            }
+        }
+
+        bytes
+    }
+
+    // Decodes a single complete unescaped frame, including start delimiter and
+    // checksum.
+    pub fn from_data(raw: &[u8]) -> Result<ApiFrame, Error> {
+        ApiFrame::from_data_with_mode(raw, ApiMode::Unescaped)
+    }
+
+    pub fn from_data_with_mode(raw: &[u8], mode: ApiMode) -> Result<ApiFrame, Error> {
+        let mut decoder = ApiDecoder::with_mode(mode);
+        decoder.push(raw);
+
+        match decoder.next_frame() {
+            Some(frame) => frame,
+            None => Err(ApiError::NotEnoughData(raw.get(3).cloned().unwrap_or(0)).into()),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut frame = vec![self.frame_type()];
+        frame.extend_from_slice(&self.data());
+
+        let mut bytes = Vec::with_capacity(frame.len() + 4);
+
+        bytes.write_u8(START_DELIMITER).unwrap();
+        bytes.write_u16::<BigEndian>(frame.len() as u16).unwrap();
+        bytes.extend_from_slice(&frame);
+        bytes.write_u8(calculate_checksum(&frame)).unwrap();
+
+        bytes
+    }
+
+    pub fn encode(&self, mode: ApiMode) -> Vec<u8> {
+        let bytes = self.as_bytes();
+
+        match mode {
+            ApiMode::Unescaped => bytes,
+            ApiMode::Escaped => {
+                let mut escaped = vec![START_DELIMITER];
+                escaped.extend_from_slice(&escape(&bytes[1..]));
+                escaped
+            }
+        }
+    }
+}
+
+// Incremental frame decoder. Bytes can be pushed in arbitrarily sized chunks;
+// anything that does not form a valid frame is skipped until the next start
+// delimiter.
+pub struct ApiDecoder {
+    mode: ApiMode,
+    buffer: Vec<u8>,
+}
+
+impl Default for ApiDecoder {
+    fn default() -> ApiDecoder {
+        ApiDecoder::new()
+    }
+}
+
+impl ApiDecoder {
+    pub fn new() -> ApiDecoder {
+        ApiDecoder::with_mode(ApiMode::Unescaped)
+    }
+
+    pub fn with_mode(mode: ApiMode) -> ApiDecoder {
+        ApiDecoder {
+            mode,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn mode(&self) -> ApiMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: ApiMode) {
+        self.mode = mode;
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    pub fn next_frame(&mut self) -> Option<Result<ApiFrame, Error>> {
+        match self.buffer.iter().position(|&byte| byte == START_DELIMITER) {
+            Some(start) => {
+                self.buffer.drain(..start);
+            }
+            None => {
+                self.buffer.clear();
+                return None;
+            }
+        }
+
+        // Unescaped length, frame data and checksum.
+        let mut content = Vec::new();
+        let mut needed = 2;
+        let mut index = 1;
+
+        while content.len() < needed {
+            let mut byte = *self.buffer.get(index)?;
+
+            if self.mode == ApiMode::Escaped {
+                // A start delimiter is never escaped, so seeing one here means
+                // the previous frame was cut short.
+                if byte == START_DELIMITER {
+                    self.buffer.drain(..index);
+                    return Some(Err(ApiError::Truncated.into()));
+                }
+
+                if byte == ESCAPE {
+                    index += 1;
+                    byte = *self.buffer.get(index)?;
+
+                    if byte == START_DELIMITER {
+                        self.buffer.drain(..index);
+                        return Some(Err(ApiError::Truncated.into()));
+                    }
+
+                    byte ^= 0x20;
+                }
+            }
+
+            content.push(byte);
+            index += 1;
+
+            if content.len() == 2 {
+                let length = BigEndian::read_u16(&content) as usize;
+
+                if length == 0 || length > MAX_FRAME_LENGTH {
+                    self.buffer.remove(0);
+                    return Some(Err(ApiError::InvalidLength(length).into()));
+                }
+
+                needed = length + 3;
+            }
+        }
+
+        let frame = &content[2..needed - 1];
+
+        if calculate_checksum(frame) != content[needed - 1] {
+            self.buffer.remove(0);
+            return Some(Err(ApiError::InvalidChecksum.into()));
+        }
+
+        self.buffer.drain(..index);
+
+        Some(ApiFrame::parse(frame[0], &frame[1..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ATNJ with frame ID 0x52, from the XBee user guide.
+    const AT_NJ: [u8; 8] = [0x7E, 0x00, 0x04, 0x08, 0x52, 0x4E, 0x4A, 0x0D];
+
+    fn at_nj() -> ApiFrame {
+        ApiFrame::AtCommand {
+            frame_id: 0x52,
+            command: "NJ".into(),
+            parameter: Vec::new(),
+        }
+    }
+
+    fn decode_all(decoder: &mut ApiDecoder, stream: &[u8], chunk: usize) -> (Vec<ApiFrame>, usize) {
+        let mut frames = Vec::new();
+        let mut errors = 0;
+
+        for data in stream.chunks(chunk) {
+            decoder.push(data);
+
+            while let Some(result) = decoder.next_frame() {
+                match result {
+                    Ok(frame) => frames.push(frame),
+                    Err(_) => errors += 1,
+                }
+            }
+        }
+
+        (frames, errors)
+    }
+
+    #[test]
+    fn encodes_and_decodes_at_command() {
+        assert_eq!(at_nj().as_bytes(), AT_NJ.to_vec());
+        assert_eq!(ApiFrame::from_data(&AT_NJ).unwrap(), at_nj());
+    }
+
+    // Node identification indicator for a router whose NI is a single space,
+    // from the XBee user guide.
+    const NODE_ID: [u8; 36] = [
+        0x7E, 0x00, 0x20, 0x95, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA, 0x7D, 0x84, 0x02, 0x7D,
+        0x84, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA, 0x20, 0x00, 0xFF, 0xFE, 0x01, 0x01, 0xC1,
+        0x05, 0x10, 0x1E, 0x1B,
+    ];
+
+    fn node_id(identifier: &str) -> ApiFrame {
+        ApiFrame::NodeIdentification(NodeIdentification {
+            src64: 0x0013_A200_4052_2BAA,
+            src16: 0x7D84,
+            options: ReceiveOptions::from_bits(0x02),
+            remote64: 0x0013_A200_4052_2BAA,
+            remote16: 0x7D84,
+            identifier: identifier.into(),
+            parent16: 0xFFFE,
+            device_type: DeviceType::Router,
+            source_event: SourceEvent::PushButton,
+            profile: 0xC105,
+            manufacturer: 0x101E,
+        })
+    }
+
+    #[test]
+    fn encodes_and_decodes_node_identification() {
+        assert_eq!(node_id(" ").as_bytes(), NODE_ID.to_vec());
+        assert_eq!(ApiFrame::from_data(&NODE_ID).unwrap(), node_id(" "));
+
+        let empty = node_id("");
+        assert_eq!(ApiFrame::from_data(&empty.as_bytes()).unwrap(), empty);
+    }
+
+    #[test]
+    fn rejects_node_identification_without_terminator() {
+        let frame = ApiFrame::Unknown {
+            frame_type: 0x95,
+            data: NODE_ID[4..26].to_vec(),
+        };
+
+        assert!(ApiFrame::from_data(&frame.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let mut raw = AT_NJ;
+        raw[7] ^= 0xFF;
+
+        assert!(ApiFrame::from_data(&raw).is_err());
+    }
+
+    #[test]
+    fn decoder_resyncs_after_garbage_and_bad_frames() {
+        let mut bad = AT_NJ;
+        bad[7] ^= 0xFF;
+
+        let mut stream = vec![0x01, 0x02];
+        stream.extend_from_slice(&bad);
+        stream.extend_from_slice(&AT_NJ);
+        stream.push(0x55);
+        stream.extend_from_slice(&AT_NJ);
+
+        for &chunk in &[1, 3, stream.len()] {
+            let (frames, errors) = decode_all(&mut ApiDecoder::new(), &stream, chunk);
+
+            assert_eq!(frames, vec![at_nj(), at_nj()]);
+            assert_eq!(errors, 1);
+        }
+    }
+
+    #[test]
+    fn escapes_reserved_bytes() {
+        let frame = ApiFrame::Unknown {
+            frame_type: 0x42,
+            data: vec![START_DELIMITER, ESCAPE, XON, XOFF, 0x05],
+        };
+
+        let encoded = frame.encode(ApiMode::Escaped);
+
+        assert!(!encoded[1..].contains(&START_DELIMITER));
+        assert!(!encoded[1..].contains(&XON));
+        assert!(!encoded[1..].contains(&XOFF));
+        assert_eq!(ApiFrame::from_data_with_mode(&encoded, ApiMode::Escaped).unwrap(), frame);
+
+        let mut stream = encoded.clone();
+        stream.extend_from_slice(&encoded);
+
+        let (frames, errors) = decode_all(&mut ApiDecoder::with_mode(ApiMode::Escaped), &stream, 1);
+
+        assert_eq!(frames, vec![frame.clone(), frame]);
+        assert_eq!(errors, 0);
+    }
+
+    #[test]
+    fn escaped_decoder_reports_truncated_frames() {
+        let encoded = at_nj().encode(ApiMode::Escaped);
+
+        let mut stream = encoded[..5].to_vec();
+        stream.extend_from_slice(&encoded);
+
+        let (frames, errors) = decode_all(&mut ApiDecoder::with_mode(ApiMode::Escaped), &stream, 2);
+
+        assert_eq!(frames, vec![at_nj()]);
+        assert_eq!(errors, 1);
+    }
+}
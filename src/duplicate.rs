@@ -0,0 +1,78 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+// Remembers recently seen (origin, packet ID) pairs so a packet that arrives
+// more than once is only handled the first time. The oldest entries are
+// forgotten once `capacity` is reached or after `max_age`.
+pub struct DuplicateCache {
+    seen: HashMap<(u32, u32), Instant>,
+    order: VecDeque<(u32, u32)>,
+    capacity: usize,
+    max_age: Duration,
+}
+
+impl DuplicateCache {
+    pub fn new(capacity: usize, max_age: Duration) -> DuplicateCache {
+        DuplicateCache {
+            seen: HashMap::new(),
+            order: VecDeque::with_capacity(capacity),
+            capacity,
+            max_age,
+        }
+    }
+
+    // Records the packet, returning false if it had already been seen.
+    pub fn insert(&mut self, origin: u32, packet_id: u32) -> bool {
+        self.expire();
+
+        if self.seen.contains_key(&(origin, packet_id)) {
+            return false;
+        }
+
+        if self.capacity == 0 {
+            return true;
+        }
+
+        while self.order.len() >= self.capacity {
+            if let Some(key) = self.order.pop_front() {
+                self.seen.remove(&key);
+            }
+        }
+
+        self.seen.insert((origin, packet_id), Instant::now());
+        self.order.push_back((origin, packet_id));
+        true
+    }
+
+    pub fn contains(&self, origin: u32, packet_id: u32) -> bool {
+        match self.seen.get(&(origin, packet_id)) {
+            Some(time) => time.elapsed() < self.max_age,
+            None => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.seen.clear();
+        self.order.clear();
+    }
+
+    pub fn expire(&mut self) {
+        while let Some(&key) = self.order.front() {
+            match self.seen.get(&key) {
+                Some(time) if time.elapsed() < self.max_age => break,
+                _ => {
+                    self.order.pop_front();
+                    self.seen.remove(&key);
+                }
+            }
+        }
+    }
+}
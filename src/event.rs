@@ -0,0 +1,19 @@
+use api::*;
+
+// Unsolicited notifications from the radio, queued on the `Xbee` until they
+// are collected with `Xbee::poll_events`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    ModemStatus(ModemStatus),
+    NodeIdentified(NodeIdentification),
+}
+
+impl Event {
+    pub fn from_frame(frame: &ApiFrame) -> Option<Event> {
+        match *frame {
+            ApiFrame::ModemStatus(status) => Some(Event::ModemStatus(status)),
+            ApiFrame::NodeIdentification(ref node) => Some(Event::NodeIdentified(node.clone())),
+            _ => None,
+        }
+    }
+}
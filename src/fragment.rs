@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use packet::{Packet, PacketFlags};
+
+// A complete payload, either from a single packet or reassembled from
+// fragments. `packet_id` is the ID of the first packet.
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub origin: u32,
+    pub dest: u32,
+    pub packet_id: u32,
+    pub flags: PacketFlags,
+    pub data: Vec<u8>,
+}
+
+struct PartialMessage {
+    dest: u32,
+    flags: PacketFlags,
+    fragments: Vec<Option<Vec<u8>>>,
+    size: usize,
+    started: Instant,
+}
+
+// Collects fragment packets until every fragment of a message has arrived.
+// Messages still incomplete after `timeout` are discarded, as are the oldest
+// messages once more than `capacity` bytes are buffered.
+pub struct Reassembler {
+    messages: HashMap<(u32, u32), PartialMessage>,
+    timeout: Duration,
+    capacity: usize,
+    size: usize,
+}
+
+impl Reassembler {
+    pub fn new(timeout: Duration, capacity: usize) -> Reassembler {
+        Reassembler {
+            messages: HashMap::new(),
+            timeout,
+            capacity,
+            size: 0,
+        }
+    }
+
+    pub fn set_limits(&mut self, timeout: Duration, capacity: usize) {
+        self.timeout = timeout;
+        self.capacity = capacity;
+        self.expire();
+    }
+
+    // Returns the message `packet` completes, if any. Packets that are not
+    // fragments are returned as a message straight away.
+    pub fn push(&mut self, packet: Packet) -> Option<Message> {
+        self.expire();
+
+        let (index, count, data) = match packet.fragment() {
+            Some((index, count, data)) => (index as usize, count as usize, data.to_vec()),
+            None if packet.flags.fragment => return None,
+            None => {
+                return Some(Message {
+                    origin: packet.origin,
+                    dest: packet.dest,
+                    packet_id: packet.packet_id,
+                    flags: packet.flags,
+                    data: packet.data,
+                });
+            }
+        };
+
+        if index >= count {
+            return None;
+        }
+
+        let key = (packet.origin, packet.packet_id.wrapping_sub(index as u32));
+
+        while self.size + data.len() > self.capacity {
+            if !self.discard_oldest() {
+                return None;
+            }
+        }
+
+        let complete = {
+            let message = self.messages.entry(key).or_insert_with(|| PartialMessage {
+                dest: packet.dest,
+                flags: PacketFlags { fragment: false, ..packet.flags },
+                fragments: vec![None; count],
+                size: 0,
+                started: Instant::now(),
+            });
+
+            // A fragment count that disagrees with earlier fragments means the
+            // key was reused; only the first message is kept.
+            if message.fragments.len() != count || message.fragments[index].is_some() {
+                return None;
+            }
+
+            message.size += data.len();
+            message.fragments[index] = Some(data);
+            message.fragments.iter().all(Option::is_some)
+        };
+
+        self.size = self.messages.values().map(|message| message.size).sum();
+
+        if !complete {
+            return None;
+        }
+
+        let message = self.messages.remove(&key)?;
+        self.size -= message.size;
+
+        Some(Message {
+            origin: key.0,
+            dest: message.dest,
+            packet_id: key.1,
+            flags: message.flags,
+            data: message.fragments.into_iter().flatten().flatten().collect(),
+        })
+    }
+
+    // Number of messages waiting on fragments.
+    pub fn pending(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+        self.size = 0;
+    }
+
+    pub fn expire(&mut self) {
+        let timeout = self.timeout;
+        self.messages.retain(|_, message| message.started.elapsed() < timeout);
+        self.size = self.messages.values().map(|message| message.size).sum();
+
+        while self.size > self.capacity && self.discard_oldest() {}
+    }
+
+    fn discard_oldest(&mut self) -> bool {
+        let oldest = self.messages.iter()
+            .min_by_key(|&(_, message)| message.started)
+            .map(|(&key, _)| key);
+
+        match oldest.and_then(|key| self.messages.remove(&key)) {
+            Some(message) => {
+                self.size -= message.size;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use packet::{Check, PacketSequence};
+
+    fn reassembler() -> Reassembler {
+        Reassembler::new(Duration::from_secs(10), 4096)
+    }
+
+    fn fragments(data: &[u8]) -> Vec<Packet> {
+        let mut sequence = PacketSequence::starting_at(0xFFFF_FFFE);
+        Packet::fragments(1, 2, &mut sequence, data, 40, Check::Crc16).unwrap()
+    }
+
+    #[test]
+    fn reassembles_fragments_in_any_order() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut packets = fragments(&data);
+        assert!(packets.len() > 2);
+
+        packets.reverse();
+        let last = packets.pop().unwrap();
+
+        let mut reassembler = reassembler();
+
+        for packet in packets {
+            assert!(reassembler.push(packet).is_none());
+        }
+
+        let message = reassembler.push(last).unwrap();
+
+        assert_eq!(message.data, data);
+        assert_eq!(message.packet_id, 0xFFFF_FFFE);
+        assert!(!message.flags.fragment);
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn ignores_repeated_fragments() {
+        let data: Vec<u8> = (0..100).collect();
+        let packets = fragments(&data);
+
+        let mut reassembler = reassembler();
+        assert!(reassembler.push(packets[0].clone()).is_none());
+        assert!(reassembler.push(packets[0].clone()).is_none());
+
+        let message = packets[1..].iter().cloned().filter_map(|packet| reassembler.push(packet)).next();
+        assert_eq!(message.unwrap().data, data);
+    }
+
+    #[test]
+    fn drops_the_oldest_message_over_capacity() {
+        let mut sequence = PacketSequence::new();
+        let older = Packet::fragments(1, 2, &mut sequence, &[0; 100], 40, Check::Crc16).unwrap();
+        let newer = Packet::fragments(1, 2, &mut sequence, &[1; 100], 40, Check::Crc16).unwrap();
+        let (older_id, newer_id) = (older[0].packet_id, newer[0].packet_id);
+
+        let mut reassembler = Reassembler::new(Duration::from_secs(10), 30);
+
+        for packet in older.into_iter().take(2) {
+            reassembler.push(packet);
+        }
+
+        std::thread::sleep(Duration::from_millis(5));
+
+        for packet in newer.into_iter().take(2) {
+            reassembler.push(packet);
+        }
+
+        assert_eq!(reassembler.pending(), 1);
+        assert!(reassembler.size <= 30);
+        assert!(!reassembler.messages.contains_key(&(1, older_id)));
+        assert!(reassembler.messages.contains_key(&(1, newer_id)));
+    }
+
+    #[test]
+    fn drops_messages_after_the_timeout() {
+        let mut reassembler = Reassembler::new(Duration::from_millis(20), 4096);
+        let packets = fragments(&[0; 100]);
+
+        reassembler.push(packets[0].clone());
+        assert_eq!(reassembler.pending(), 1);
+
+        std::thread::sleep(Duration::from_millis(30));
+        reassembler.expire();
+        assert_eq!(reassembler.pending(), 0);
+
+        // The rest of the message no longer completes it.
+        assert!(packets[1..].iter().cloned().all(|packet| reassembler.push(packet).is_none()));
+    }
+
+    #[test]
+    fn passes_whole_packets_through() {
+        let packet = Packet::new(1, 2, 3, b"whole").unwrap();
+        let message = reassembler().push(packet).unwrap();
+
+        assert_eq!(message.data, b"whole");
+        assert_eq!(message.packet_id, 3);
+    }
+}
@@ -0,0 +1,189 @@
+use failure::Error;
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::time::{Duration, Instant};
+
+use api::{ApiError, ApiFrame};
+
+struct PendingRequest {
+    deadline: Instant,
+    response: Option<ApiFrame>,
+}
+
+// Hands out API frame IDs (1 - 255) and tracks the requests waiting on them.
+// IDs are allocated round robin so a late response is unlikely to be matched
+// to a newer request that reused its ID.
+pub struct FrameIdAllocator {
+    pending: HashMap<u8, PendingRequest>,
+    next: u8,
+}
+
+impl Default for FrameIdAllocator {
+    fn default() -> FrameIdAllocator {
+        FrameIdAllocator::new()
+    }
+}
+
+impl FrameIdAllocator {
+    pub fn new() -> FrameIdAllocator {
+        FrameIdAllocator {
+            pending: HashMap::new(),
+            next: 1,
+        }
+    }
+
+    pub fn allocate(&mut self, timeout: Duration) -> Result<u8, Error> {
+        self.expire();
+
+        for _ in 0..255 {
+            let id = self.next;
+            // Frame ID 0 tells the radio not to send a response.
+            self.next = self.next.wrapping_add(1).max(1);
+
+            if let Entry::Vacant(entry) = self.pending.entry(id) {
+                entry.insert(PendingRequest {
+                    deadline: Instant::now() + timeout,
+                    response: None,
+                });
+
+                return Ok(id);
+            }
+        }
+
+        Err(ApiError::NoFreeFrameId.into())
+    }
+
+    // Stores `frame` as the response to the pending request with the same
+    // frame ID. Frames that do not answer a pending request are given back.
+    pub fn complete(&mut self, frame: ApiFrame) -> Option<ApiFrame> {
+        if let Some(request) = frame.frame_id().and_then(|id| self.pending.get_mut(&id)) {
+            if request.response.is_none() {
+                request.response = Some(frame);
+                return None;
+            }
+        }
+
+        Some(frame)
+    }
+
+    // Returns the response to `id` once it has arrived, freeing the ID. Fails
+    // with a timeout once the request's deadline has passed.
+    pub fn take(&mut self, id: u8) -> Result<Option<ApiFrame>, Error> {
+        let expired = match self.pending.get_mut(&id) {
+            Some(request) => {
+                if let Some(response) = request.response.take() {
+                    self.pending.remove(&id);
+                    return Ok(Some(response));
+                }
+
+                Instant::now() >= request.deadline
+            }
+            None => true,
+        };
+
+        if expired {
+            self.pending.remove(&id);
+            return Err(ApiError::Timeout(id).into());
+        }
+
+        Ok(None)
+    }
+
+    pub fn release(&mut self, id: u8) {
+        self.pending.remove(&id);
+    }
+
+    pub fn is_pending(&self, id: u8) -> bool {
+        self.pending.contains_key(&id)
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    // Frees the IDs of requests whose deadline has passed without a response.
+    pub fn expire(&mut self) {
+        let now = Instant::now();
+        self.pending.retain(|_, request| request.response.is_some() || request.deadline > now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::CommandStatus;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn response(frame_id: u8) -> ApiFrame {
+        ApiFrame::AtCommandResponse {
+            frame_id,
+            command: "NJ".into(),
+            status: CommandStatus::Ok,
+            data: Vec::new(),
+        }
+    }
+
+    fn api_error(err: Error) -> ApiError {
+        err.downcast::<ApiError>().unwrap()
+    }
+
+    #[test]
+    fn never_issues_zero_after_wrapping() {
+        let mut allocator = FrameIdAllocator::new();
+
+        for expected in (1..=255).chain(1..=10) {
+            let id = allocator.allocate(TIMEOUT).unwrap();
+            assert_eq!(id, expected);
+            allocator.release(id);
+        }
+    }
+
+    #[test]
+    fn fails_once_every_id_is_pending() {
+        let mut allocator = FrameIdAllocator::new();
+
+        for _ in 0..255 {
+            allocator.allocate(TIMEOUT).unwrap();
+        }
+
+        assert_eq!(allocator.pending(), 255);
+        assert!(matches!(api_error(allocator.allocate(TIMEOUT).unwrap_err()), ApiError::NoFreeFrameId));
+
+        allocator.release(7);
+        assert_eq!(allocator.allocate(TIMEOUT).unwrap(), 7);
+    }
+
+    #[test]
+    fn take_times_out_after_the_deadline() {
+        let mut allocator = FrameIdAllocator::new();
+        let id = allocator.allocate(Duration::from_millis(10)).unwrap();
+
+        assert!(allocator.take(id).unwrap().is_none());
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(matches!(api_error(allocator.take(id).unwrap_err()), ApiError::Timeout(timed_out) if timed_out == id));
+        assert!(!allocator.is_pending(id));
+    }
+
+    #[test]
+    fn hands_responses_to_their_request() {
+        let mut allocator = FrameIdAllocator::new();
+        let id = allocator.allocate(TIMEOUT).unwrap();
+
+        assert!(allocator.complete(response(id)).is_none());
+        assert_eq!(allocator.take(id).unwrap(), Some(response(id)));
+        assert!(!allocator.is_pending(id));
+    }
+
+    #[test]
+    fn hands_back_late_responses_for_released_ids() {
+        let mut allocator = FrameIdAllocator::new();
+        let id = allocator.allocate(TIMEOUT).unwrap();
+        allocator.release(id);
+
+        assert_eq!(allocator.complete(response(id)), Some(response(id)));
+    }
+}
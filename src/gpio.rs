@@ -0,0 +1,429 @@
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use failure::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Pin {
+    D0,
+    D1,
+    D2,
+    D3,
+    D4,
+    D5,
+    D6,
+    D7,
+    D8,
+    D9,
+    P0,
+    P1,
+    P2,
+    P3,
+    P4,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PinMode {
+    Disabled,
+    CommissioningButton,
+    AssociateLed,
+    RssiPwm,
+    Rts,
+    Cts,
+    Adc,
+    DigitalInput,
+    DigitalOutputLow,
+    DigitalOutputHigh,
+    Rs485TxEnableLow,
+    Rs485TxEnableHigh,
+    Unknown(u8),
+}
+
+#[derive(Debug, Fail)]
+pub enum GpioError {
+    #[fail(display = "Pin {:?} does not support mode {:?}.", _0, _1)]
+    UnsupportedMode(Pin, PinMode),
+    #[fail(display = "Could not parse IO sample.")]
+    InvalidSample,
+}
+
+pub const PINS: [Pin; 15] = [
+    Pin::D0, Pin::D1, Pin::D2, Pin::D3, Pin::D4,
+    Pin::D5, Pin::D6, Pin::D7, Pin::D8, Pin::D9,
+    Pin::P0, Pin::P1, Pin::P2, Pin::P3, Pin::P4,
+];
+
+impl Pin {
+    pub fn command(&self) -> &'static str {
+        match *self {
+            Pin::D0 => "D0",
+            Pin::D1 => "D1",
+            Pin::D2 => "D2",
+            Pin::D3 => "D3",
+            Pin::D4 => "D4",
+            Pin::D5 => "D5",
+            Pin::D6 => "D6",
+            Pin::D7 => "D7",
+            Pin::D8 => "D8",
+            Pin::D9 => "D9",
+            Pin::P0 => "P0",
+            Pin::P1 => "P1",
+            Pin::P2 => "P2",
+            Pin::P3 => "P3",
+            Pin::P4 => "P4",
+        }
+    }
+
+    // Bit position of the pin in digital sample masks (DIO0 - DIO14).
+    pub fn dio(&self) -> u8 {
+        PINS.iter().position(|pin| pin == self).unwrap() as u8
+    }
+
+    pub fn from_dio(dio: u8) -> Option<Pin> {
+        PINS.get(dio as usize).cloned()
+    }
+
+    // Bit position of the pin in the ATPR pull-up mask.
+    pub fn pull_up_bit(&self) -> u8 {
+        match *self {
+            Pin::D4 => 0,
+            Pin::D3 => 1,
+            Pin::D2 => 2,
+            Pin::D1 => 3,
+            Pin::D0 => 4,
+            Pin::D6 => 5,
+            Pin::D8 => 6,
+            Pin::P4 => 7,
+            Pin::D5 => 8,
+            Pin::D9 => 9,
+            Pin::P2 => 10,
+            Pin::P0 => 11,
+            Pin::P1 => 12,
+            Pin::D7 => 13,
+            Pin::P3 => 14,
+        }
+    }
+}
+
+impl PinMode {
+    pub fn value(&self) -> u8 {
+        match *self {
+            PinMode::Disabled => 0,
+            PinMode::CommissioningButton
+            | PinMode::AssociateLed
+            | PinMode::RssiPwm
+            | PinMode::Rts
+            | PinMode::Cts => 1,
+            PinMode::Adc => 2,
+            PinMode::DigitalInput => 3,
+            PinMode::DigitalOutputLow => 4,
+            PinMode::DigitalOutputHigh => 5,
+            PinMode::Rs485TxEnableLow => 6,
+            PinMode::Rs485TxEnableHigh => 7,
+            PinMode::Unknown(value) => value,
+        }
+    }
+
+    pub fn from_value(pin: Pin, value: u8) -> PinMode {
+        match (pin, value) {
+            (_, 0) => PinMode::Disabled,
+            (Pin::D0, 1) => PinMode::CommissioningButton,
+            (Pin::D5, 1) => PinMode::AssociateLed,
+            (Pin::D6, 1) => PinMode::Rts,
+            (Pin::D7, 1) => PinMode::Cts,
+            (Pin::P0, 1) => PinMode::RssiPwm,
+            (Pin::D0, 2) | (Pin::D1, 2) | (Pin::D2, 2) | (Pin::D3, 2) => PinMode::Adc,
+            (_, 3) => PinMode::DigitalInput,
+            (_, 4) => PinMode::DigitalOutputLow,
+            (_, 5) => PinMode::DigitalOutputHigh,
+            (Pin::D7, 6) => PinMode::Rs485TxEnableLow,
+            (Pin::D7, 7) => PinMode::Rs485TxEnableHigh,
+            (_, value) => PinMode::Unknown(value),
+        }
+    }
+
+    pub fn supported_by(&self, pin: Pin) -> bool {
+        match *self {
+            PinMode::Unknown(_) => false,
+            _ => PinMode::from_value(pin, self.value()) == *self,
+        }
+    }
+}
+
+pub fn check_mode(pin: Pin, mode: PinMode) -> Result<(), Error> {
+    ensure!(mode.supported_by(pin), GpioError::UnsupportedMode(pin, mode));
+    Ok(())
+}
+
+pub fn pull_up_mask(pins: &[Pin]) -> u16 {
+    pins.iter().fold(0, |mask, pin| mask | 1 << pin.pull_up_bit())
+}
+
+pub fn pull_up_pins(mask: u16) -> Vec<Pin> {
+    PINS.iter()
+        .filter(|pin| mask & 1 << pin.pull_up_bit() != 0)
+        .cloned()
+        .collect()
+}
+
+pub fn dio_mask(pins: &[Pin]) -> u16 {
+    pins.iter().fold(0, |mask, pin| mask | 1 << pin.dio())
+}
+
+pub fn dio_pins(mask: u16) -> Vec<Pin> {
+    PINS.iter()
+        .filter(|pin| mask & 1 << pin.dio() != 0)
+        .cloned()
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AnalogChannel {
+    Ad0,
+    Ad1,
+    Ad2,
+    Ad3,
+    Ad4,
+    Ad5,
+    SupplyVoltage,
+}
+
+pub const ANALOG_CHANNELS: [AnalogChannel; 7] = [
+    AnalogChannel::Ad0,
+    AnalogChannel::Ad1,
+    AnalogChannel::Ad2,
+    AnalogChannel::Ad3,
+    AnalogChannel::Ad4,
+    AnalogChannel::Ad5,
+    AnalogChannel::SupplyVoltage,
+];
+
+// ADC reference voltage of ZigBee modules; readings are 10 bits wide.
+pub const VREF_MILLIVOLTS: u32 = 1200;
+
+impl AnalogChannel {
+    pub fn bit(&self) -> u8 {
+        match *self {
+            AnalogChannel::Ad0 => 0,
+            AnalogChannel::Ad1 => 1,
+            AnalogChannel::Ad2 => 2,
+            AnalogChannel::Ad3 => 3,
+            // AD4 and AD5 only exist on 802.15.4 (Series 1) modules.
+            AnalogChannel::Ad4 => 4,
+            AnalogChannel::Ad5 => 5,
+            AnalogChannel::SupplyVoltage => 7,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PinLevels {
+    pub mask: u16,
+    pub levels: u16,
+}
+
+impl PinLevels {
+    pub fn level(&self, pin: Pin) -> Option<bool> {
+        let bit = 1 << pin.dio();
+
+        if self.mask & bit != 0 {
+            Some(self.levels & bit != 0)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IoSample {
+    pub digital: PinLevels,
+    pub analog_mask: u8,
+    // Raw ADC readings, one per bit set in `analog_mask`, lowest bit first.
+    pub analog: Vec<u16>,
+}
+
+impl IoSample {
+    // Parses the response to ATIS in command mode, which is one hex value per
+    // line: sample set count, digital mask, analog mask, then the samples.
+    pub fn from_response(resp: &str) -> Result<IoSample, Error> {
+        let values = resp.split('\r')
+            .filter(|line| !line.is_empty())
+            .map(|line| u16::from_str_radix(line.trim(), 16))
+            .collect::<Result<Vec<u16>, _>>()
+            .map_err(|_| GpioError::InvalidSample)?;
+
+        ensure!(values.len() >= 3, GpioError::InvalidSample);
+
+        let mask = values[1];
+        let analog_mask = values[2] as u8;
+        let mut samples = values[3..].iter();
+
+        let levels = if mask != 0 {
+            *samples.next().ok_or(GpioError::InvalidSample)?
+        } else {
+            0
+        };
+
+        let analog = samples
+            .take(analog_mask.count_ones() as usize)
+            .cloned()
+            .collect::<Vec<u16>>();
+
+        ensure!(analog.len() == analog_mask.count_ones() as usize, GpioError::InvalidSample);
+
+        Ok(IoSample {
+            digital: PinLevels {
+                mask,
+                levels: levels & mask,
+            },
+            analog_mask,
+            analog,
+        })
+    }
+
+    // Parses the binary form of a sample used in API frames: sample set count,
+    // digital mask, analog mask, digital samples if any, then analog samples.
+    pub fn from_bytes(raw: &[u8]) -> Result<IoSample, Error> {
+        ensure!(raw.len() >= 4, GpioError::InvalidSample);
+
+        let mask = BigEndian::read_u16(&raw[1..]);
+        let analog_mask = raw[3];
+        let mut offset = 4;
+
+        let levels = if mask != 0 {
+            ensure!(raw.len() >= offset + 2, GpioError::InvalidSample);
+            offset += 2;
+            BigEndian::read_u16(&raw[offset - 2..])
+        } else {
+            0
+        };
+
+        let count = analog_mask.count_ones() as usize;
+        ensure!(raw.len() >= offset + count * 2, GpioError::InvalidSample);
+
+        let analog = raw[offset..offset + count * 2]
+            .chunks(2)
+            .map(BigEndian::read_u16)
+            .collect::<Vec<u16>>();
+
+        Ok(IoSample {
+            digital: PinLevels {
+                mask,
+                levels: levels & mask,
+            },
+            analog_mask,
+            analog,
+        })
+    }
+
+    // Parses the 802.15.4 (Series 1) sample format, which can carry several
+    // samples behind a single channel indicator: bits 0 - 8 select DIO0 - DIO8
+    // and bits 9 - 14 select AD0 - AD5.
+    pub fn from_legacy_bytes(raw: &[u8]) -> Result<Vec<IoSample>, Error> {
+        ensure!(raw.len() >= 3, GpioError::InvalidSample);
+
+        let count = raw[0] as usize;
+        let indicator = BigEndian::read_u16(&raw[1..]);
+        let mask = indicator & 0x01FF;
+        let analog_mask = (indicator >> 9) as u8 & 0x3F;
+        let analog_count = analog_mask.count_ones() as usize;
+        let size = if mask != 0 { 2 } else { 0 } + analog_count * 2;
+
+        ensure!(raw.len() >= 3 + count * size, GpioError::InvalidSample);
+
+        let samples = raw[3..3 + count * size]
+            .chunks(size.max(1))
+            .take(count)
+            .map(|chunk| {
+                let (levels, analog) = if mask != 0 {
+                    (BigEndian::read_u16(chunk), &chunk[2..])
+                } else {
+                    (0, chunk)
+                };
+
+                IoSample {
+                    digital: PinLevels {
+                        mask,
+                        levels: levels & mask,
+                    },
+                    analog_mask,
+                    analog: analog.chunks(2).map(BigEndian::read_u16).collect(),
+                }
+            })
+            .collect();
+
+        Ok(samples)
+    }
+
+    pub fn legacy_bytes(samples: &[IoSample]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let (mask, analog_mask) = samples.first()
+            .map_or((0, 0), |sample| (sample.digital.mask, sample.analog_mask));
+
+        bytes.write_u8(samples.len() as u8).unwrap();
+        bytes.write_u16::<BigEndian>((analog_mask as u16 & 0x3F) << 9 | mask & 0x01FF).unwrap();
+
+        for sample in samples {
+            if mask != 0 {
+                bytes.write_u16::<BigEndian>(sample.digital.levels).unwrap();
+            }
+
+            for &value in &sample.analog {
+                bytes.write_u16::<BigEndian>(value).unwrap();
+            }
+        }
+
+        bytes
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        bytes.write_u8(1).unwrap();
+        bytes.write_u16::<BigEndian>(self.digital.mask).unwrap();
+        bytes.write_u8(self.analog_mask).unwrap();
+
+        if self.digital.mask != 0 {
+            bytes.write_u16::<BigEndian>(self.digital.levels).unwrap();
+        }
+
+        for &value in &self.analog {
+            bytes.write_u16::<BigEndian>(value).unwrap();
+        }
+
+        bytes
+    }
+
+    pub fn level(&self, pin: Pin) -> Option<bool> {
+        self.digital.level(pin)
+    }
+
+    pub fn levels(&self) -> Vec<(Pin, bool)> {
+        PINS.iter()
+            .filter_map(|&pin| self.level(pin).map(|level| (pin, level)))
+            .collect()
+    }
+
+    pub fn analog_values(&self) -> Vec<(AnalogChannel, u16)> {
+        ANALOG_CHANNELS.iter()
+            .filter_map(|&channel| self.analog(channel).map(|value| (channel, value)))
+            .collect()
+    }
+
+    pub fn analog(&self, channel: AnalogChannel) -> Option<u16> {
+        let bit = channel.bit();
+
+        if self.analog_mask & 1 << bit == 0 {
+            return None;
+        }
+
+        let index = (self.analog_mask & ((1 << bit) - 1)).count_ones() as usize;
+        self.analog.get(index).cloned()
+    }
+
+    pub fn millivolts(&self, channel: AnalogChannel) -> Option<u32> {
+        self.millivolts_with_reference(channel, VREF_MILLIVOLTS)
+    }
+
+    pub fn millivolts_with_reference(&self, channel: AnalogChannel, vref: u32) -> Option<u32> {
+        self.analog(channel).map(|raw| raw as u32 * vref / 0x3FF)
+    }
+}
@@ -1,28 +1,126 @@
 #![feature(io)]
 extern crate byteorder;
 #[macro_use] extern crate failure;
-#[macro_use] extern crate lazy_static;
-extern crate parking_lot;
 extern crate serial;
 
 use failure::Error;
 use serial::prelude::*;
 
+use std::collections::VecDeque;
 use std::ffi::OsStr;
 use std::io::prelude::*;
 use std::time::{Duration, Instant};
 
+pub mod api;
+pub mod duplicate;
+pub mod event;
+pub mod fragment;
+pub mod frame_id;
+pub mod gpio;
+pub mod link;
 pub mod packet;
+pub mod reliable;
+pub mod remote;
+pub mod routing;
+pub mod source_route;
 
+use api::*;
+use duplicate::*;
+use event::*;
+use fragment::*;
+use frame_id::*;
+use gpio::*;
+use link::*;
 use packet::*;
+use reliable::*;
+use remote::*;
+use routing::*;
+use source_route::*;
+
+fn duration_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + duration.subsec_nanos() as u64 / 1_000_000
+}
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+// Frames read while waiting for something else are kept up to this limit.
+const MAX_PENDING_FRAMES: usize = 64;
+const MAX_PENDING_EVENTS: usize = 64;
+// Route records are resent after every many-to-one broadcast, so a route
+// that has not been refreshed for this long is likely stale.
+const SOURCE_ROUTE_MAX_AGE: Duration = Duration::from_secs(600);
+// Largest RF payload of an 802.15.4 radio, used when ATNP is not supported.
+const DEFAULT_MAX_PAYLOAD: usize = 100;
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+const REASSEMBLY_CAPACITY: usize = 64 * 1024;
+const DUPLICATE_CACHE_CAPACITY: usize = 256;
+const DUPLICATE_MAX_AGE: Duration = Duration::from_secs(60);
+const ROUTE_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
+const ACK_TIMEOUT: Duration = Duration::from_millis(500);
+const ACK_RETRIES: u32 = 3;
+const ACK_BACKOFF: u32 = 2;
+
+pub type ClusterHandler = Box<dyn FnMut(&ReceivedMessage) + Send>;
+
+struct Binding {
+    endpoint: u8,
+    cluster: u16,
+    handler: ClusterHandler,
+}
 
 pub struct Xbee {
     port: serial::SystemPort,
     last_time: Instant,
+    link_monitor: Option<LinkMonitor>,
+    api: Option<ApiMode>,
+    family: RadioFamily,
+    decoder: ApiDecoder,
+    requests: FrameIdAllocator,
+    frames: VecDeque<ApiFrame>,
+    events: VecDeque<Event>,
+    associated: Option<bool>,
+    bindings: Vec<Binding>,
+    packet_dest64: u64,
+    source_routes: Option<SourceRouteCache>,
+    packet_decoder: PacketDecoder,
+    reassembler: Reassembler,
+    max_payload: Option<usize>,
+    node_address: Option<u32>,
+    sequence: PacketSequence,
+    packet_check: Check,
+    relay: bool,
+    hop_limit: u8,
+    seen: DuplicateCache,
+    routes: Option<RouteTable>,
+    // Packets for this node read while waiting for something else.
+    received: VecDeque<Packet>,
+    // Acknowledgements received, as (acknowledged node, acknowledging node,
+    // packet ID).
+    acks: VecDeque<(u32, u32, u32)>,
+    delivered: DuplicateCache,
+    retry: RetryPolicy,
 }
 
 impl Xbee {
+    // Opens the radio and detects whether it was left in transparent or API
+    // mode. A radio that does not answer is assumed to be transparent.
     pub fn new<T: AsRef<OsStr> + ?Sized>(port: &T) -> Result<Xbee, Error> {
+        let mut xbee = Xbee::open(port, None)?;
+        let _ = xbee.detect_mode();
+        Ok(xbee)
+    }
+
+    // Opens a radio that is already configured for API operation (ATAP1/2).
+    // The frame set is picked from ATHV; a radio that does not answer keeps
+    // the Zigbee one.
+    pub fn with_api_mode<T: AsRef<OsStr> + ?Sized>(port: &T, mode: ApiMode) -> Result<Xbee, Error> {
+        let mut xbee = Xbee::open(port, Some(mode))?;
+        let _ = xbee.detect_family();
+        xbee.read_packet_settings();
+        Ok(xbee)
+    }
+
+    fn open<T: AsRef<OsStr> + ?Sized>(port: &T, api: Option<ApiMode>) -> Result<Xbee, Error> {
         let mut port = serial::open(port)?;
 
         port.reconfigure(&|settings| {
@@ -39,6 +137,31 @@ impl Xbee {
         Ok(Xbee {
             port: port,
             last_time: Instant::now(),
+            link_monitor: None,
+            api,
+            family: RadioFamily::Zigbee,
+            decoder: ApiDecoder::with_mode(api.unwrap_or(ApiMode::Unescaped)),
+            requests: FrameIdAllocator::new(),
+            frames: VecDeque::new(),
+            events: VecDeque::new(),
+            associated: None,
+            bindings: Vec::new(),
+            packet_dest64: BROADCAST_64,
+            source_routes: None,
+            packet_decoder: PacketDecoder::new(),
+            reassembler: Reassembler::new(REASSEMBLY_TIMEOUT, REASSEMBLY_CAPACITY),
+            max_payload: None,
+            node_address: None,
+            sequence: PacketSequence::new(),
+            packet_check: Check::Sum,
+            relay: false,
+            hop_limit: DEFAULT_TTL,
+            seen: DuplicateCache::new(DUPLICATE_CACHE_CAPACITY, DUPLICATE_MAX_AGE),
+            routes: None,
+            received: VecDeque::new(),
+            acks: VecDeque::new(),
+            delivered: DuplicateCache::new(DUPLICATE_CACHE_CAPACITY, DUPLICATE_MAX_AGE),
+            retry: RetryPolicy::new(ACK_TIMEOUT, ACK_RETRIES, ACK_BACKOFF),
         })
     }
 
@@ -62,149 +185,1251 @@ impl Xbee {
         output
     }
 
+    // In relay and routing mode, packets addressed to other nodes are passed
+    // on rather than returned, and duplicates are dropped.
     pub fn read_packet(&mut self) -> Result<Packet, Error> {
+        let deadline = Instant::now() + READ_TIMEOUT;
+
+        loop {
+            if let Some(packet) = self.received.pop_front() {
+                return Ok(packet);
+            }
+
+            let now = Instant::now();
+            ensure!(now < deadline, PacketError::Timeout);
+
+            if let Some(packet) = self.next_packet(deadline - now)? {
+                return Ok(packet);
+            }
+        }
+    }
+
+    // Reads the next packet for this node. Returns None on timeout, and also
+    // as soon as a packet has been consumed here (relayed, routed, an
+    // acknowledgement or a duplicate), so callers waiting on the route table
+    // or on acknowledgements can check them again.
+    fn next_packet(&mut self, timeout: Duration) -> Result<Option<Packet>, Error> {
+        let deadline = Instant::now() + timeout;
         let mut buffer = [0; 1024];
-        let mut data = Vec::new();
-        let mut length = 0;
-        let mut tries = 0;
-        let mut started = 0;
-        
+
+        let node = if self.relay || self.routes.is_some() {
+            Some(self.node_address())
+        } else {
+            None
+        };
+
+        loop {
+            if let Some(result) = self.packet_decoder.next_packet() {
+                let packet = result?;
+
+                let handled = match node {
+                    Some(node) => !self.handle_packet(node, &packet),
+                    None => false,
+                };
+
+                if handled || !self.accept_packet(&packet) {
+                    return Ok(None);
+                }
+
+                return Ok(Some(packet));
+            }
+
+            let now = Instant::now();
+
+            if now >= deadline {
+                return Ok(None);
+            }
+
+            if self.api.is_some() {
+                let frame = self.take_frame(deadline - now, |frame| frame.message().is_some())?;
+
+                if let Some(message) = frame.as_ref().and_then(ApiFrame::message) {
+                    self.packet_decoder.push(&message.data);
+                }
+
+                continue;
+            }
+
+            if let Ok(amount) = self.port.read(&mut buffer[..]) {
+                self.packet_decoder.push(&buffer[..amount]);
+            }
+        }
+    }
+
+    // Reads packets until a whole message has arrived, reassembling fragmented
+    // ones. Unlike `read_packet` this never returns a single fragment.
+    pub fn read_message(&mut self) -> Result<Message, Error> {
+        let deadline = Instant::now() + READ_TIMEOUT;
+
         loop {
-            if started <= 1 {
-                let mut startbyte = [0; 1];
-
-                if self.port.read_exact(&mut startbyte).is_ok() {
-                    if started == 0 && startbyte[0] == 0xA3 {
-                        started += 1;
-                    } else if started == 1 && startbyte[0] == 0xFF {
-                        started += 1;
-                    } else {
-                        started = 0;
+            let packet = self.read_packet()?;
+
+            if let Some(message) = self.reassembler.push(packet) {
+                return Ok(message);
+            }
+
+            ensure!(Instant::now() < deadline, PacketError::Timeout);
+        }
+    }
+
+    pub fn set_reassembly_limits(&mut self, timeout: Duration, capacity: usize) {
+        self.reassembler.set_limits(timeout, capacity);
+    }
+
+    // Sends `data` from this node, split into fragments when it does not fit
+    // in one packet.
+    pub fn send_packet(&mut self, dest: u32, data: &[u8]) -> Result<usize, Error> {
+        let origin = self.node_address();
+        self.send_packet_from(origin, dest, data)
+    }
+
+    // Sends `data` on behalf of `origin`, for gateways forwarding packets from
+    // other nodes. Packet IDs still come from this node's sequence.
+    pub fn send_packet_from(&mut self, origin: u32, dest: u32, data: &[u8]) -> Result<usize, Error> {
+        let (packets, dest64) = self.prepare_packets(origin, dest, data, false)?;
+        let mut sent = 0;
+
+        for packet in &packets {
+            sent += self.write_packet(packet, dest64)?;
+        }
+
+        Ok(sent)
+    }
+
+    // Sends `data` from this node and waits until `dest` has acknowledged
+    // every packet, retransmitting the rest as the retry policy allows.
+    pub fn send_reliable(&mut self, dest: u32, data: &[u8]) -> Result<(), Error> {
+        ensure!(dest != BROADCAST, PacketError::InvalidDestination(dest));
+
+        let origin = self.node_address();
+        let (mut pending, dest64) = self.prepare_packets(origin, dest, data, true)?;
+
+        for attempt in 0..=self.retry.retries {
+            for packet in &pending {
+                // A failed transmission is retried like a lost one.
+                let _ = self.write_packet(packet, dest64);
+            }
+
+            let deadline = Instant::now() + self.retry.timeout(attempt);
+
+            loop {
+                let acks = &mut self.acks;
+
+                pending.retain(|packet| {
+                    match acks.iter().position(|&ack| ack == (origin, dest, packet.packet_id)) {
+                        Some(index) => {
+                            acks.remove(index);
+                            false
+                        }
+                        None => true,
                     }
+                });
+
+                if pending.is_empty() {
+                    return Ok(());
                 }
-            } else {
-                if let Ok(amount) = self.port.read(&mut buffer[..]) {
-                    data.extend_from_slice(&buffer[..amount]);
+
+                let now = Instant::now();
+
+                if now >= deadline {
+                    break;
                 }
 
-                if length == 0 && data.len() >= 15 {
-                    length = data[14] as usize;
-                } else if length > 0 && data.len() >= 15 + length {
-                    return Packet::from_data(&data);
+                if let Ok(Some(packet)) = self.next_packet(deadline - now) {
+                    self.keep_packet(packet);
                 }
             }
+        }
 
-            ensure!(tries < 1000, PacketError::Timeout);
-            tries += 1;
+        // The route may be broken, so it is discovered again next time.
+        if let Some(ref mut routes) = self.routes {
+            routes.remove(dest);
         }
+
+        Err(PacketError::DeliveryFailed(pending[0].packet_id).into())
     }
 
-    pub fn send_packet(&mut self, dest: u32, data: &[u8]) -> Result<usize, Error> {
-        let packet = Packet::new(dest, data);
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    // Splits `data` into packets for `dest`, with the next hop filled in when
+    // routing. Returns them with the radio address to transmit them to.
+    fn prepare_packets(&mut self, origin: u32, dest: u32, data: &[u8], ack_requested: bool)
+        -> Result<(Vec<Packet>, u64), Error>
+    {
+        let max_size = self.max_payload();
+        let mut dest64 = self.packet_dest64;
+        let mut next_hop = BROADCAST;
+
+        if self.routes.is_some() && dest != BROADCAST {
+            let node = self.node_address();
+            next_hop = self.find_route(node, dest)?;
+            // The next hop in the header picks the node that handles the
+            // packet, so the radio has to broadcast it.
+            dest64 = BROADCAST_64;
+        }
+
+        let check = self.packet_check;
+        let mut packets = Packet::fragments(origin, dest, &mut self.sequence, data, max_size, check)?;
+
+        // Plain packets keep the layout picked by the check type; only the
+        // features in use move them to a newer one.
+        for packet in &mut packets {
+            if ack_requested {
+                let flags = PacketFlags { ack_requested, ..packet.flags };
+                packet.set_flags(flags);
+            }
+
+            if self.relay || self.routes.is_some() {
+                packet.set_ttl(self.hop_limit);
+            }
+
+            if next_hop != BROADCAST {
+                packet.set_next_hop(next_hop);
+            }
+        }
+
+        Ok((packets, dest64))
+    }
+
+    fn keep_packet(&mut self, packet: Packet) {
+        if self.received.len() >= MAX_PENDING_FRAMES {
+            self.received.pop_front();
+        }
+
+        self.received.push_back(packet);
+    }
+
+    // Records acknowledgements and acknowledges packets that ask for it.
+    // Returns false for packets that must not be delivered: acknowledgements
+    // and retransmissions of packets already delivered.
+    fn accept_packet(&mut self, packet: &Packet) -> bool {
+        if let Some(id) = packet.acked_id() {
+            // Every node hears every acknowledgement, and packet IDs only
+            // differ between senders by chance.
+            if self.node_address.is_some_and(|node| node != packet.dest) {
+                return false;
+            }
+
+            if self.acks.len() >= MAX_PENDING_FRAMES {
+                self.acks.pop_front();
+            }
+
+            self.acks.push_back((packet.dest, packet.origin, id));
+
+            // An acknowledgement shows the route to its sender still works.
+            if let Some(ref mut routes) = self.routes {
+                routes.refresh(packet.origin);
+            }
+
+            return false;
+        }
+
+        // Without a known node address every packet that reaches the radio is
+        // taken to be for this node.
+        let node = self.node_address.unwrap_or(packet.dest);
+
+        if !packet.flags.ack_requested || packet.dest != node || node == BROADCAST {
+            return true;
+        }
+
+        // Duplicates are acknowledged again, as the first acknowledgement may
+        // have been lost.
+        let id = self.sequence.next_id();
+        let mut ack = packet.ack(id);
+        let mut dest64 = self.packet_dest64;
+
+        if self.relay || self.routes.is_some() {
+            ack.set_ttl(self.hop_limit);
+        }
+
+        if let Some(route) = self.routes.as_ref().and_then(|routes| routes.route(ack.dest)) {
+            ack.set_next_hop(route.next_hop);
+            dest64 = BROADCAST_64;
+        }
+
+        let _ = self.write_packet(&ack, dest64);
+
+        self.delivered.insert(packet.origin, packet.packet_id)
+    }
+
+    fn write_packet(&mut self, packet: &Packet, dest64: u64) -> Result<usize, Error> {
+        if self.api.is_some() {
+            let bytes = packet.as_bytes();
+            let result = self.transmit(dest64, UNKNOWN_16, &bytes, TransmitOptions::new())?;
+
+            ensure!(result.delivered(), ApiError::DeliveryFailed(result.delivery));
+            return Ok(bytes.len());
+        }
 
         self.write_raw(&packet.as_bytes())
     }
 
+    // Relays, forwards or consumes `packet` as relay and routing mode
+    // require. Returns whether the packet should be delivered here.
+    fn handle_packet(&mut self, node: u32, packet: &Packet) -> bool {
+        // Routed packets meant for another hop were only overheard.
+        if packet.next_hop != BROADCAST && packet.next_hop != node {
+            return false;
+        }
+
+        // Retransmissions for this node get through, to be acknowledged again.
+        let duplicate = !self.seen.insert(packet.origin, packet.packet_id);
+        let acknowledged = packet.flags.ack_requested && packet.dest == node;
+
+        if packet.origin == node || (duplicate && !acknowledged) {
+            return false;
+        }
+
+        if let Some(ref mut routes) = self.routes {
+            // Hearing from a neighbour shows every route through it still
+            // works. Sending never does, as broadcasts are not acknowledged.
+            routes.refresh_via(packet.sender);
+            routes.update(packet.sender, packet.sender, 1);
+
+            if packet.flags.routing {
+                self.handle_route_message(node, packet);
+                return false;
+            }
+
+            if packet.next_hop == node && packet.dest != node {
+                self.forward_packet(node, packet);
+                return false;
+            }
+        }
+
+        if self.relay && packet.dest != node {
+            if let Some(relayed) = packet.relayed(node) {
+                // Relaying is best effort; a failure must not lose the packet
+                // for this node.
+                let _ = self.write_packet(&relayed, BROADCAST_64);
+            }
+        }
+
+        packet.dest == node || packet.dest == BROADCAST
+    }
+
+    // Relay mode floods packets through the network: every node rebroadcasts
+    // packets meant for others until their TTL runs out.
+    pub fn set_relay(&mut self, relay: bool) {
+        self.relay = relay;
+        self.seen.clear();
+    }
+
+    pub fn relay(&self) -> bool {
+        self.relay
+    }
+
+    // Routing mode sends packets to known nodes along a learned path instead
+    // of flooding them. Routes are discovered on demand and forgotten after
+    // `lifetime` without traffic from their next hop or an acknowledgement
+    // through them.
+    pub fn enable_routing(&mut self, lifetime: Duration) {
+        match self.routes {
+            Some(ref mut routes) => routes.set_lifetime(lifetime),
+            None => self.routes = Some(RouteTable::new(lifetime)),
+        }
+    }
+
+    pub fn disable_routing(&mut self) {
+        self.routes = None;
+    }
+
+    pub fn route_table(&self) -> Option<&RouteTable> {
+        self.routes.as_ref()
+    }
+
+    fn learn_route(&mut self, dest: u32, next_hop: u32, hops: u8) {
+        if let Some(ref mut routes) = self.routes {
+            routes.update(dest, next_hop, hops);
+        }
+    }
+
+    // Next hop towards `dest`, flooding a route request and waiting for the
+    // reply when no route is known yet.
+    fn find_route(&mut self, node: u32, dest: u32) -> Result<u32, Error> {
+        if let Some(route) = self.routes.as_ref().and_then(|routes| routes.route(dest)) {
+            return Ok(route.next_hop);
+        }
+
+        self.send_route_message(node, BROADCAST, RouteMessage::Request { target: dest, hops: 0 })?;
+
+        let deadline = Instant::now() + ROUTE_DISCOVERY_TIMEOUT;
+
+        loop {
+            let now = Instant::now();
+            ensure!(now < deadline, PacketError::NoRoute(dest));
+
+            // Packets for this node that arrive meanwhile are kept for
+            // `read_packet`.
+            if let Ok(Some(packet)) = self.next_packet(deadline - now) {
+                self.keep_packet(packet);
+            }
+
+            if let Some(route) = self.routes.as_ref().and_then(|routes| routes.route(dest)) {
+                return Ok(route.next_hop);
+            }
+        }
+    }
+
+    fn send_route_message(&mut self, node: u32, dest: u32, message: RouteMessage) -> Result<(), Error> {
+        let next_hop = if dest == BROADCAST {
+            BROADCAST
+        } else {
+            let route = self.routes.as_ref().and_then(|routes| routes.route(dest));
+            route.ok_or(PacketError::NoRoute(dest))?.next_hop
+        };
+
+        let id = self.sequence.next_id();
+        let mut packet = Packet::with_check(node, dest, id, &message.as_bytes(), self.packet_check)?;
+        let flags = PacketFlags { routing: true, ..packet.flags };
+
+        packet.set_flags(flags).set_ttl(self.hop_limit).set_next_hop(next_hop);
+        self.seen.insert(node, id);
+
+        // Routed packets are broadcast by the radio; the next hop in the header
+        // picks the node that handles them.
+        self.write_packet(&packet, BROADCAST_64)?;
+        Ok(())
+    }
+
+    fn handle_route_message(&mut self, node: u32, packet: &Packet) {
+        let message = match RouteMessage::from_bytes(&packet.data) {
+            Ok(message) => message,
+            Err(_) => return,
+        };
+
+        match message {
+            RouteMessage::Request { target, hops } => {
+                let hops = hops.saturating_add(1);
+                self.learn_route(packet.origin, packet.sender, hops);
+
+                if target == node {
+                    let reply = RouteMessage::Reply { target, hops: 0 };
+                    let _ = self.send_route_message(node, packet.origin, reply);
+                } else if let Some(mut relayed) = packet.relayed(node) {
+                    if relayed.set_data(&RouteMessage::Request { target, hops }.as_bytes()).is_ok() {
+                        let _ = self.write_packet(&relayed, BROADCAST_64);
+                    }
+                }
+            }
+            RouteMessage::Reply { target, hops } => {
+                let hops = hops.saturating_add(1);
+                self.learn_route(target, packet.sender, hops);
+
+                if packet.dest != node {
+                    let mut reply = packet.clone();
+
+                    if reply.set_data(&RouteMessage::Reply { target, hops }.as_bytes()).is_ok() {
+                        self.forward_packet(node, &reply);
+                    }
+                }
+            }
+            RouteMessage::Error { unreachable } => {
+                // Only routes through the node reporting the error are broken.
+                let broken = match self.routes.as_ref().and_then(|routes| routes.route(unreachable)) {
+                    Some(route) => route.next_hop == packet.sender,
+                    None => false,
+                };
+
+                if broken {
+                    if let Some(ref mut routes) = self.routes {
+                        routes.remove(unreachable);
+                    }
+                }
+
+                if packet.dest != node {
+                    self.forward_packet(node, packet);
+                }
+            }
+        }
+    }
+
+    // Passes `packet` one hop closer to its destination, reporting a route
+    // error back to its origin when that is not possible.
+    fn forward_packet(&mut self, node: u32, packet: &Packet) {
+        let next_hop = self.routes.as_ref()
+            .and_then(|routes| routes.route(packet.dest))
+            .map(|route| route.next_hop);
+
+        if let Some(next_hop) = next_hop {
+            let forwarded = match packet.forwarded(node, next_hop) {
+                Some(forwarded) => forwarded,
+                // Out of hops, so the packet is dropped.
+                None => return,
+            };
+
+            if self.write_packet(&forwarded, BROADCAST_64).is_ok() {
+                return;
+            }
+
+            if let Some(ref mut routes) = self.routes {
+                routes.remove(packet.dest);
+            }
+        }
+
+        // Route errors are not reported themselves, or they could bounce
+        // between nodes forever.
+        if packet.flags.routing {
+            if let Ok(RouteMessage::Error { .. }) = RouteMessage::from_bytes(&packet.data) {
+                return;
+            }
+        }
+
+        let error = RouteMessage::Error { unreachable: packet.dest };
+        let _ = self.send_route_message(node, packet.origin, error);
+    }
+
+    // Number of times packets sent from this node may be relayed.
+    pub fn set_hop_limit(&mut self, hops: u8) {
+        self.hop_limit = hops;
+    }
+
+    // Check type of the packets this node sends. `Check::Sum`, the default,
+    // sends them in the version 0 layout understood by older nodes as long as
+    // no acknowledgements, relaying or routing are asked for.
+    pub fn set_packet_check(&mut self, check: Check) {
+        self.packet_check = check;
+    }
+
+    pub fn packet_check(&self) -> Check {
+        self.packet_check
+    }
+
+    // Radio that `send_packet` transmits to in API mode. In transparent mode
+    // the radio's own DH/DL are used instead.
+    pub fn set_packet_destination(&mut self, dest64: u64) {
+        self.packet_dest64 = dest64;
+    }
+
+    // Origin of the packets this node sends. Defaults to the radio's 16-bit
+    // address (ATMY) as read when the radio was opened, or 0 when it could not
+    // be read; use `set_node_address` to pick one.
+    pub fn node_address(&self) -> u32 {
+        self.node_address.unwrap_or(0)
+    }
+
+    pub fn set_node_address(&mut self, addr: u32) {
+        self.node_address = Some(addr);
+    }
+
+    // Largest RF payload the radio can send (ATNP), as read when the radio was
+    // opened. Radios that did not report it are assumed to take
+    // `DEFAULT_MAX_PAYLOAD`.
+    pub fn max_payload(&self) -> usize {
+        self.max_payload.unwrap_or(DEFAULT_MAX_PAYLOAD)
+    }
+
     pub fn connect(&mut self) -> Result<bool, Error> {
+        if self.api.is_some() {
+            return Ok(true);
+        }
+
         self.write_raw(b"+++")?;
         let resp = self.read_raw();
 
         Ok(resp == "OK\r")
     }
 
+    // Uses the association last reported by the radio when there is one.
     pub fn connected(&self) -> bool {
-        self.last_time.elapsed().as_secs() < 8
+        self.associated.unwrap_or_else(|| self.last_time.elapsed().as_secs() < 8)
     }
 
-    pub fn id(&mut self) -> Result<u16, Error> {
-        self.write_raw(b"ATID\r")?;
+    // Network association as last reported by a modem status frame, if any has
+    // been seen.
+    pub fn associated(&self) -> Option<bool> {
+        self.associated
+    }
 
-        let mut resp = self.read_raw();
-        resp.pop();
+    fn push_event(&mut self, event: Event) {
+        match event {
+            Event::ModemStatus(status) => {
+                if let Some(associated) = status.associated() {
+                    self.associated = Some(associated);
+                }
+            }
+            Event::NodeIdentified(_) => {}
+        }
 
-        let val = u16::from_str_radix(&resp, 16)?;
-        Ok(val)
+        if self.events.len() >= MAX_PENDING_EVENTS {
+            self.events.pop_front();
+        }
+
+        self.events.push_back(event);
     }
 
-    pub fn set_id(&mut self, id: u16) -> Result<bool, Error> {
-        self.write_raw(format!("ATID{:x}\r", id).as_bytes())?;
-        let resp = self.read_raw();
-        Ok(resp == "OK\r")
+    // Reads whatever is waiting on the port and returns the events received
+    // since the last call.
+    pub fn poll_events(&mut self) -> Result<Vec<Event>, Error> {
+        while let Some(frame) = self.poll_frame()? {
+            self.keep_frame(frame);
+        }
+
+        Ok(self.events.drain(..).collect())
     }
 
-    pub fn address(&mut self) -> Result<u16, Error> {
-        self.write_raw(b"ATMY\r")?;
+    pub fn write_frame(&mut self, frame: &ApiFrame) -> Result<usize, Error> {
+        let mode = self.api.ok_or(ApiError::NotInApiMode)?;
+        self.write_raw(&frame.encode(mode))
+    }
 
-        let mut resp = self.read_raw();
-        resp.pop();
+    fn poll_frame(&mut self) -> Result<Option<ApiFrame>, Error> {
+        ensure!(self.api.is_some(), ApiError::NotInApiMode);
+
+        loop {
+            match self.decoder.next_frame() {
+                Some(Ok(frame)) => {
+                    if let Some(event) = Event::from_frame(&frame) {
+                        self.push_event(event);
+                        continue;
+                    }
+
+                    if self.record_route(&frame) {
+                        continue;
+                    }
 
-        let val = u16::from_str_radix(&resp, 16)?;
-        Ok(val)
+                    if self.dispatch(&frame) {
+                        continue;
+                    }
+
+                    if let Some(frame) = self.requests.complete(frame) {
+                        return Ok(Some(frame));
+                    }
+                }
+                Some(Err(_)) => continue,
+                None => {}
+            }
+
+            let mut buffer = [0; 1024];
+
+            match self.port.read(&mut buffer[..]) {
+                Ok(amount) if amount > 0 => self.decoder.push(&buffer[..amount]),
+                _ => return Ok(None),
+            }
+        }
     }
 
-    pub fn set_address(&mut self, addr: u16) -> Result<bool, Error> {
-        self.write_raw(format!("ATMY{:x}\r", addr).as_bytes())?;
-        let resp = self.read_raw();
-        Ok(resp == "OK\r")
+    // Caches the route carried by a Route Record frame while many-to-one
+    // routing is enabled. Returns whether the frame was consumed.
+    fn record_route(&mut self, frame: &ApiFrame) -> bool {
+        let routes = match self.source_routes {
+            Some(ref mut routes) => routes,
+            None => return false,
+        };
+
+        match *frame {
+            ApiFrame::RouteRecord { src64, src16, ref hops, .. } => {
+                routes.record(src64, src16, hops.clone());
+                true
+            }
+            _ => false,
+        }
     }
 
-    pub fn dh(&mut self) -> Result<u16, Error> {
-        self.write_raw(b"ATDH\r")?;
+    // Hands explicit receive frames to the handler bound to their destination
+    // endpoint and cluster. Returns whether the frame was consumed.
+    fn dispatch(&mut self, frame: &ApiFrame) -> bool {
+        let message = match *frame {
+            ApiFrame::ExplicitRxIndicator(ref message) => message,
+            _ => return false,
+        };
+
+        let address = match message.explicit {
+            Some(address) => address,
+            None => return false,
+        };
+
+        let binding = self.bindings.iter_mut().find(|binding| {
+            binding.endpoint == address.dest_endpoint && binding.cluster == address.cluster
+        });
+
+        match binding {
+            Some(binding) => {
+                (binding.handler)(message);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn bind(&mut self, endpoint: u8, cluster: u16, handler: ClusterHandler) {
+        self.unbind(endpoint, cluster);
+
+        self.bindings.push(Binding {
+            endpoint,
+            cluster,
+            handler,
+        });
+    }
+
+    pub fn unbind(&mut self, endpoint: u8, cluster: u16) {
+        self.bindings.retain(|binding| binding.endpoint != endpoint || binding.cluster != cluster);
+    }
+
+    fn keep_frame(&mut self, frame: ApiFrame) {
+        if self.frames.len() >= MAX_PENDING_FRAMES {
+            self.frames.pop_front();
+        }
+
+        self.frames.push_back(frame);
+    }
+
+    // Returns the first frame accepted by `accept`, reading from the port for
+    // up to `timeout`. Frames that are not accepted are kept for later calls.
+    fn take_frame<F>(&mut self, timeout: Duration, accept: F) -> Result<Option<ApiFrame>, Error>
+        where F: Fn(&ApiFrame) -> bool
+    {
+        if let Some(index) = self.frames.iter().position(&accept) {
+            return Ok(self.frames.remove(index));
+        }
+
+        let start = Instant::now();
+
+        while start.elapsed() < timeout {
+            if let Some(frame) = self.poll_frame()? {
+                if accept(&frame) {
+                    return Ok(Some(frame));
+                }
+
+                self.keep_frame(frame);
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn read_frame(&mut self) -> Result<ApiFrame, Error> {
+        let frame = self.take_frame(READ_TIMEOUT, |_| true)?;
+        Ok(frame.ok_or(ApiError::ReadTimeout)?)
+    }
+
+    // Sends the frame built for a newly allocated frame ID and waits for the
+    // matching response.
+    fn request<F>(&mut self, build: F) -> Result<ApiFrame, Error>
+        where F: FnOnce(u8) -> ApiFrame
+    {
+        let frame_id = self.requests.allocate(RESPONSE_TIMEOUT)?;
+
+        if let Err(err) = self.write_frame(&build(frame_id)) {
+            self.requests.release(frame_id);
+            return Err(err);
+        }
+
+        loop {
+            if let Some(frame) = self.requests.take(frame_id)? {
+                return Ok(frame);
+            }
+
+            if let Some(frame) = self.poll_frame()? {
+                self.keep_frame(frame);
+            }
+        }
+    }
+
+    pub fn receive(&mut self) -> Result<ReceivedMessage, Error> {
+        let frame = self.take_frame(READ_TIMEOUT, |frame| frame.message().is_some())?;
+
+        match frame {
+            Some(ApiFrame::ReceivePacket(message))
+            | Some(ApiFrame::ExplicitRxIndicator(message))
+            | Some(ApiFrame::Receive64(message))
+            | Some(ApiFrame::Receive16(message)) => Ok(message),
+            Some(frame) => Err(ApiError::UnexpectedFrame(frame.frame_type()).into()),
+            None => Err(ApiError::ReadTimeout.into()),
+        }
+    }
+
+    pub fn at_command(&mut self, cmd: &str, parameter: &[u8]) -> Result<Vec<u8>, Error> {
+        ensure!(cmd.len() == 2, CommandError::InvalidCommand(cmd.into()));
+
+        let response = self.request(|frame_id| ApiFrame::AtCommand {
+            frame_id,
+            command: cmd.into(),
+            parameter: parameter.into(),
+        })?;
+
+        match response {
+            ApiFrame::AtCommandResponse { status, data, .. } => {
+                status.check(cmd)?;
+                Ok(data)
+            }
+            frame => Err(ApiError::UnexpectedFrame(frame.frame_type()).into()),
+        }
+    }
+
+    pub fn read_io_sample(&mut self) -> Result<IoSampleMessage, Error> {
+        let frame = self.take_frame(READ_TIMEOUT, |frame| frame.io_sample().is_some())?;
+
+        match frame {
+            Some(ApiFrame::IoSampleIndicator(message))
+            | Some(ApiFrame::IoReceive64(message))
+            | Some(ApiFrame::IoReceive16(message)) => Ok(message),
+            Some(frame) => Err(ApiError::UnexpectedFrame(frame.frame_type()).into()),
+            None => Err(ApiError::ReadTimeout.into()),
+        }
+    }
+
+    pub fn remote(&mut self, dest64: u64) -> RemoteXbee<'_> {
+        RemoteXbee::new(self, dest64, UNKNOWN_16)
+    }
+
+    pub fn transmit(&mut self, dest64: u64, dest16: u16, payload: &[u8], options: TransmitOptions)
+        -> Result<TransmitResult, Error>
+    {
+        let family = self.family;
+        let data = payload.to_vec();
+
+        let dest16 = match family {
+            RadioFamily::Zigbee => self.send_source_route(dest64, dest16)?,
+            RadioFamily::Series1 => dest16,
+        };
+
+        let response = self.request(|frame_id| match family {
+            RadioFamily::Zigbee => {
+                ApiFrame::TransmitRequest { frame_id, dest64, dest16, options, data }
+            }
+            RadioFamily::Series1 if dest16 != UNKNOWN_16 => {
+                ApiFrame::Transmit16 { frame_id, dest16, options, data }
+            }
+            RadioFamily::Series1 => ApiFrame::Transmit64 { frame_id, dest64, options, data },
+        })?;
+
+        match response {
+            ApiFrame::TransmitStatus { result, .. } => Ok(result),
+            ApiFrame::TxStatus { delivery, .. } => Ok(TransmitResult {
+                dest16,
+                retries: 0,
+                delivery,
+                discovery: DiscoveryStatus::NoOverhead,
+            }),
+            frame => Err(ApiError::UnexpectedFrame(frame.frame_type()).into()),
+        }
+    }
+
+    pub fn transmit_explicit(&mut self, dest64: u64, dest16: u16, address: ExplicitAddress, payload: &[u8],
+                             options: TransmitOptions) -> Result<TransmitResult, Error>
+    {
+        let dest16 = self.send_source_route(dest64, dest16)?;
+
+        let response = self.request(|frame_id| ApiFrame::ExplicitTransmit {
+            frame_id,
+            dest64,
+            dest16,
+            address,
+            options,
+            data: payload.into(),
+        })?;
+
+        match response {
+            ApiFrame::TransmitStatus { result, .. } => Ok(result),
+            frame => Err(ApiError::UnexpectedFrame(frame.frame_type()).into()),
+        }
+    }
+
+    // Queues a parameter change that is only applied by a later AC or WR.
+    pub fn queue_parameter(&mut self, cmd: &str, parameter: &[u8]) -> Result<(), Error> {
+        ensure!(cmd.len() == 2, CommandError::InvalidCommand(cmd.into()));
+
+        let response = self.request(|frame_id| ApiFrame::QueueParameterValue {
+            frame_id,
+            command: cmd.into(),
+            parameter: parameter.into(),
+        })?;
+
+        match response {
+            ApiFrame::AtCommandResponse { status, .. } => status.check(cmd),
+            frame => Err(ApiError::UnexpectedFrame(frame.frame_type()).into()),
+        }
+    }
+
+    fn query(&mut self, cmd: &str) -> Result<String, Error> {
+        self.write_raw(format!("AT{}\r", cmd).as_bytes())?;
 
         let mut resp = self.read_raw();
         resp.pop();
 
-        let val = u16::from_str_radix(&resp, 16)?;
-        Ok(val)
+        Ok(resp)
+    }
+
+    pub fn register(&mut self, cmd: &str) -> Result<u64, Error> {
+        match self.api {
+            Some(_) => Ok(parameter_value(&self.at_command(cmd, &[])?)),
+            None => Ok(u64::from_str_radix(&self.query(cmd)?, 16)?),
+        }
+    }
+
+    pub fn set_register(&mut self, cmd: &str, value: u64) -> Result<bool, Error> {
+        match self.api {
+            Some(_) => {
+                self.at_command(cmd, &parameter_bytes(value))?;
+                Ok(true)
+            }
+            None => {
+                self.write_raw(format!("AT{}{:x}\r", cmd, value).as_bytes())?;
+                let resp = self.read_raw();
+                Ok(resp == "OK\r")
+            }
+        }
+    }
+
+    pub fn queue_register(&mut self, cmd: &str, value: u64) -> Result<bool, Error> {
+        match self.api {
+            Some(_) => {
+                self.queue_parameter(cmd, &parameter_bytes(value))?;
+                Ok(true)
+            }
+            None => self.set_register(cmd, value),
+        }
+    }
+
+    pub fn execute(&mut self, cmd: &str) -> Result<bool, Error> {
+        match self.api {
+            Some(_) => {
+                self.at_command(cmd, &[])?;
+                Ok(true)
+            }
+            None => {
+                self.write_raw(format!("AT{}\r", cmd).as_bytes())?;
+                let resp = self.read_raw();
+                Ok(resp == "OK\r")
+            }
+        }
+    }
+
+    pub fn id(&mut self) -> Result<u16, Error> {
+        let val = self.register("ID")?;
+        Ok(val as u16)
+    }
+
+    pub fn set_id(&mut self, id: u16) -> Result<bool, Error> {
+        self.set_register("ID", id as u64)
+    }
+
+    pub fn address(&mut self) -> Result<u16, Error> {
+        let val = self.register("MY")?;
+        Ok(val as u16)
+    }
+
+    pub fn set_address(&mut self, addr: u16) -> Result<bool, Error> {
+        let set = self.set_register("MY", addr as u64)?;
+
+        if set {
+            self.node_address = Some(addr as u32);
+        }
+
+        Ok(set)
+    }
+
+    pub fn dh(&mut self) -> Result<u16, Error> {
+        let val = self.register("DH")?;
+        Ok(val as u16)
     }
 
     pub fn set_dh(&mut self, dh: u16) -> Result<bool, Error> {
-        self.write_raw(format!("ATDH{:x}\r", dh).as_bytes())?;
-        let resp = self.read_raw();
-        Ok(resp == "OK\r")
+        self.set_register("DH", dh as u64)
     }
 
     pub fn dl(&mut self) -> Result<u16, Error> {
-        self.write_raw(b"ATDL\r")?;
+        let val = self.register("DL")?;
+        Ok(val as u16)
+    }
 
-        let mut resp = self.read_raw();
-        resp.pop();
+    pub fn set_dl(&mut self, dl: u16) -> Result<bool, Error> {
+        self.set_register("DL", dl as u64)
+    }
 
-        let val = u16::from_str_radix(&resp, 16)?;
-        Ok(val)
+    pub fn pin_mode(&mut self, pin: Pin) -> Result<PinMode, Error> {
+        let val = self.register(pin.command())?;
+        Ok(PinMode::from_value(pin, val as u8))
     }
 
-    pub fn set_dl(&mut self, dl: u16) -> Result<bool, Error> {
-        self.write_raw(format!("ATDL{:x}\r", dl).as_bytes())?;
-        let resp = self.read_raw();
-        Ok(resp == "OK\r")
+    pub fn set_pin_mode(&mut self, pin: Pin, mode: PinMode) -> Result<bool, Error> {
+        check_mode(pin, mode)?;
+        self.set_register(pin.command(), mode.value() as u64)
     }
 
-    pub fn edit_config<F>(&mut self, edit: F) -> Result<(), Error> 
-        where F: FnOnce(&mut XbeeConfig)
-    {
-        let mut config = XbeeConfig::new();
+    pub fn pull_ups(&mut self) -> Result<Vec<Pin>, Error> {
+        let val = self.register("PR")?;
+        Ok(pull_up_pins(val as u16))
+    }
 
-        edit(&mut config);
+    pub fn set_pull_ups(&mut self, pins: &[Pin]) -> Result<bool, Error> {
+        self.set_register("PR", pull_up_mask(pins) as u64)
+    }
+
+    pub fn pin_levels(&mut self) -> Result<PinLevels, Error> {
+        Ok(self.force_sample()?.digital)
+    }
 
-        if let Some(id) = config.id {
-            self.set_id(id)?;
+    pub fn force_sample(&mut self) -> Result<IoSample, Error> {
+        match self.api {
+            Some(_) => {
+                let data = self.at_command("IS", &[])?;
+                IoSample::from_bytes(&data)
+            }
+            None => {
+                let resp = self.query("IS")?;
+                IoSample::from_response(&resp)
+            }
+        }
+    }
+
+    pub fn sample_rate(&mut self) -> Result<Duration, Error> {
+        let val = self.register("IR")?;
+        Ok(Duration::from_millis(val))
+    }
+
+    pub fn set_sample_rate(&mut self, rate: Duration) -> Result<bool, Error> {
+        self.set_register("IR", duration_millis(rate))
+    }
+
+    pub fn change_detection(&mut self) -> Result<Vec<Pin>, Error> {
+        let val = self.register("IC")?;
+        Ok(dio_pins(val as u16))
+    }
+
+    pub fn set_change_detection(&mut self, pins: &[Pin]) -> Result<bool, Error> {
+        self.set_register("IC", dio_mask(pins) as u64)
+    }
+
+    pub fn set_sample_destination(&mut self, dest: u64) -> Result<bool, Error> {
+        let high = self.set_register("DH", dest >> 32)?;
+        let low = self.set_register("DL", dest & 0xFFFF_FFFF)?;
+        Ok(high && low)
+    }
+
+    pub fn api_mode(&mut self) -> Result<ApiMode, Error> {
+        let val = self.register("AP")?;
+        ApiMode::from_ap(val as u8)
+    }
+
+    pub fn set_api_mode(&mut self, mode: ApiMode) -> Result<bool, Error> {
+        self.set_register("AP", mode.ap() as u64)
+    }
+
+    // Host side operating mode: None while the radio is in transparent mode.
+    pub fn mode(&self) -> Option<ApiMode> {
+        self.api
+    }
+
+    fn use_mode(&mut self, api: Option<ApiMode>) {
+        self.api = api;
+        self.decoder.clear();
+        self.decoder.set_mode(api.unwrap_or(ApiMode::Unescaped));
+    }
+
+    // Reads ATAP (and ATHV for the frame set) through command mode, which the
+    // radio accepts whatever its API setting, then matches the host side codec
+    // to it.
+    pub fn detect_mode(&mut self) -> Result<Option<ApiMode>, Error> {
+        let current = self.api;
+        self.use_mode(None);
+
+        let result = self.read_mode();
+
+        match result {
+            Ok(api) => self.use_mode(api),
+            Err(_) => self.use_mode(current),
+        }
+
+        result
+    }
+
+    fn read_mode(&mut self) -> Result<Option<ApiMode>, Error> {
+        ensure!(self.connect()?, ApiError::CommandModeFailed);
+
+        let ap = self.register("AP")?;
+        let api = match ap {
+            0 => None,
+            ap => Some(ApiMode::from_ap(ap as u8)?),
+        };
+
+        if let Ok(version) = self.register("HV") {
+            self.family = RadioFamily::from_hardware_version(version as u16);
+        }
+
+        self.read_packet_settings();
+        self.execute("CN")?;
+        Ok(api)
+    }
+
+    // Reads the radio settings the packet layer depends on. Only called while
+    // the radio takes AT commands, in command mode or API mode, as in
+    // transparent mode they would go out over the air instead.
+    fn read_packet_settings(&mut self) {
+        if let Ok(size) = self.register("NP") {
+            self.max_payload = Some(size as usize);
         }
 
-        if let Some(addr) = config.addr {
-            self.set_address(addr)?;
+        if self.node_address.is_none() {
+            if let Ok(addr) = self.address() {
+                self.node_address = Some(addr as u32);
+            }
         }
+    }
 
-        if let Some(dh) = config.dh {
-            self.set_dh(dh)?;
+    pub fn enter_api_mode(&mut self, mode: ApiMode) -> Result<(), Error> {
+        match self.api {
+            Some(current) if current == mode => return Ok(()),
+            Some(_) => {
+                self.set_register("AP", mode.ap() as u64)?;
+            }
+            None => {
+                ensure!(self.connect()?, ApiError::CommandModeFailed);
+                ensure!(self.set_register("AP", mode.ap() as u64)?, ApiError::CommandModeFailed);
+                self.execute("CN")?;
+            }
         }
 
-        if let Some(dl) = config.dl {
-            self.set_dl(dl)?;
+        self.use_mode(Some(mode));
+        Ok(())
+    }
+
+    pub fn enter_transparent_mode(&mut self) -> Result<(), Error> {
+        if self.api.is_none() {
+            return Ok(());
         }
 
-        self.write_raw(b"ATWR")?;
-        self.write_raw(b"ATAC")?;
+        self.set_register("AP", 0)?;
+        self.use_mode(None);
+        Ok(())
+    }
+
+    pub fn family(&self) -> RadioFamily {
+        self.family
+    }
+
+    pub fn set_family(&mut self, family: RadioFamily) {
+        self.family = family;
+    }
+
+    // Picks the API frame set to use from the hardware version (ATHV).
+    pub fn detect_family(&mut self) -> Result<RadioFamily, Error> {
+        let val = self.register("HV")?;
+        self.family = RadioFamily::from_hardware_version(val as u16);
+        Ok(self.family)
+    }
+
+    // Makes this radio a many-to-one concentrator (ATAR), broadcasting a route
+    // request every `interval`. The Route Record frames remote radios send back
+    // are cached and used to source route later transmissions.
+    pub fn enable_many_to_one(&mut self, interval: Duration) -> Result<bool, Error> {
+        let ar = (interval.as_secs() / 10).clamp(1, 0xFE);
+
+        if !self.set_register("AR", ar)? {
+            return Ok(false);
+        }
+
+        if self.source_routes.is_none() {
+            self.source_routes = Some(SourceRouteCache::new(SOURCE_ROUTE_MAX_AGE));
+        }
+
+        Ok(true)
+    }
+
+    pub fn disable_many_to_one(&mut self) -> Result<bool, Error> {
+        self.source_routes = None;
+        self.set_register("AR", 0xFF)
+    }
+
+    pub fn source_routes(&self) -> Option<&SourceRouteCache> {
+        self.source_routes.as_ref()
+    }
+
+    // Sends a Create Source Route frame ahead of a transmission to `dest64`
+    // when a route to it is cached. Returns the 16-bit address to transmit to,
+    // which is filled in from the route when the caller does not know it.
+    fn send_source_route(&mut self, dest64: u64, dest16: u16) -> Result<u16, Error> {
+        let route = match self.source_routes.as_mut() {
+            Some(routes) => {
+                routes.expire();
+                routes.route(dest64).cloned()
+            }
+            None => None,
+        };
+
+        let route = match route {
+            Some(route) => route,
+            None => return Ok(dest16),
+        };
+
+        // Direct neighbours need no source route.
+        if !route.hops.is_empty() {
+            self.write_frame(&ApiFrame::CreateSourceRoute {
+                frame_id: 0,
+                dest64,
+                dest16: route.dest16,
+                hops: route.hops,
+            })?;
+        }
+
+        Ok(if dest16 == UNKNOWN_16 { route.dest16 } else { dest16 })
+    }
+
+    pub fn last_rssi(&mut self) -> Result<i16, Error> {
+        let val = self.register("DB")?;
+        Ok(-(val as i16))
+    }
+
+    fn counter(&mut self, cmd: &str) -> Option<u16> {
+        self.register(cmd).ok().map(|val| val as u16)
+    }
+
+    pub fn link_sample(&mut self) -> LinkSample {
+        LinkSample {
+            time: Instant::now(),
+            rssi: self.last_rssi().ok(),
+            receive_errors: self.counter("ER"),
+            good_packets: self.counter("GD"),
+            ack_failures: self.counter("EA"),
+            transmit_failures: self.counter("TR"),
+        }
+    }
+
+    pub fn monitor_link(&mut self, interval: Duration, capacity: usize) {
+        self.link_monitor = Some(LinkMonitor::new(interval, capacity));
+    }
+
+    pub fn link_monitor(&self) -> Option<&LinkMonitor> {
+        self.link_monitor.as_ref()
+    }
+
+    pub fn poll_link(&mut self) -> Option<&LinkSample> {
+        let due = match self.link_monitor {
+            Some(ref monitor) => monitor.due(),
+            None => false,
+        };
+
+        if due {
+            let sample = self.link_sample();
+
+            if let Some(ref mut monitor) = self.link_monitor {
+                monitor.record(sample);
+            }
+        }
+
+        self.link_monitor.as_ref().and_then(LinkMonitor::latest)
+    }
+
+    pub fn edit_config<F>(&mut self, edit: F) -> Result<(), Error> 
+        where F: FnOnce(&mut XbeeConfig)
+    {
+        let mut config = XbeeConfig::new();
+
+        edit(&mut config);
+
+        for (cmd, value) in config.commands()? {
+            self.queue_register(cmd, value)?;
+        }
+
+        self.execute("WR")?;
+        self.execute("AC")?;
         Ok(())
     }
 }
@@ -214,6 +1439,10 @@ pub struct XbeeConfig {
     pub addr: Option<u16>,
     pub dh: Option<u16>,
     pub dl: Option<u16>,
+    pub pins: Vec<(Pin, PinMode)>,
+    pub pull_ups: Option<Vec<Pin>>,
+    pub sample_rate: Option<Duration>,
+    pub change_detection: Option<Vec<Pin>>,
 }
 
 impl XbeeConfig {
@@ -223,9 +1452,54 @@ impl XbeeConfig {
             addr: None,
             dh: None,
             dl: None,
+            pins: Vec::new(),
+            pull_ups: None,
+            sample_rate: None,
+            change_detection: None,
         }
     }
 
+    // AT commands and values that apply this configuration, in the order they
+    // should be sent.
+    pub fn commands(&self) -> Result<Vec<(&'static str, u64)>, Error> {
+        let mut commands = Vec::new();
+
+        if let Some(id) = self.id {
+            commands.push(("ID", id as u64));
+        }
+
+        if let Some(addr) = self.addr {
+            commands.push(("MY", addr as u64));
+        }
+
+        if let Some(dh) = self.dh {
+            commands.push(("DH", dh as u64));
+        }
+
+        if let Some(dl) = self.dl {
+            commands.push(("DL", dl as u64));
+        }
+
+        for &(pin, mode) in &self.pins {
+            check_mode(pin, mode)?;
+            commands.push((pin.command(), mode.value() as u64));
+        }
+
+        if let Some(ref pins) = self.pull_ups {
+            commands.push(("PR", pull_up_mask(pins) as u64));
+        }
+
+        if let Some(rate) = self.sample_rate {
+            commands.push(("IR", duration_millis(rate)));
+        }
+
+        if let Some(ref pins) = self.change_detection {
+            commands.push(("IC", dio_mask(pins) as u64));
+        }
+
+        Ok(commands)
+    }
+
     pub fn set_id(&mut self, id: u16) -> &mut Self {
         self.id = Some(id);
         self
@@ -245,4 +1519,25 @@ impl XbeeConfig {
         self.dl = Some(dl);
         self
     }
+
+    pub fn set_pin_mode(&mut self, pin: Pin, mode: PinMode) -> &mut Self {
+        self.pins.retain(|&(p, _)| p != pin);
+        self.pins.push((pin, mode));
+        self
+    }
+
+    pub fn set_pull_ups(&mut self, pins: &[Pin]) -> &mut Self {
+        self.pull_ups = Some(pins.to_vec());
+        self
+    }
+
+    pub fn set_sample_rate(&mut self, rate: Duration) -> &mut Self {
+        self.sample_rate = Some(rate);
+        self
+    }
+
+    pub fn set_change_detection(&mut self, pins: &[Pin]) -> &mut Self {
+        self.change_detection = Some(pins.to_vec());
+        self
+    }
 }
\ No newline at end of file
@@ -0,0 +1,68 @@
+use std::collections::VecDeque;
+use std::collections::vec_deque::Iter;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+pub struct LinkSample {
+    pub time: Instant,
+    // Signal strength of the last received packet in dBm.
+    pub rssi: Option<i16>,
+    // Counters are None when the radio does not support the command.
+    pub receive_errors: Option<u16>,
+    pub good_packets: Option<u16>,
+    pub ack_failures: Option<u16>,
+    pub transmit_failures: Option<u16>,
+}
+
+pub struct LinkMonitor {
+    interval: Duration,
+    capacity: usize,
+    samples: VecDeque<LinkSample>,
+    last_poll: Option<Instant>,
+}
+
+impl LinkMonitor {
+    pub fn new(interval: Duration, capacity: usize) -> LinkMonitor {
+        LinkMonitor {
+            interval,
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+            last_poll: None,
+        }
+    }
+
+    pub fn due(&self) -> bool {
+        match self.last_poll {
+            Some(time) => time.elapsed() >= self.interval,
+            None => true,
+        }
+    }
+
+    pub fn record(&mut self, sample: LinkSample) {
+        // Polling still counts when nothing is kept, or `due` would never
+        // clear.
+        self.last_poll = Some(sample.time);
+
+        if self.capacity == 0 {
+            return;
+        }
+
+        while self.samples.len() >= self.capacity {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(sample);
+    }
+
+    pub fn latest(&self) -> Option<&LinkSample> {
+        self.samples.back()
+    }
+
+    pub fn samples(&self) -> Iter<'_, LinkSample> {
+        self.samples.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
@@ -1,17 +1,125 @@
-use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
 use failure::Error;
-use parking_lot::RwLock;
+
+use std::io::Cursor;
 
 #[derive(Clone, Debug)]
 pub struct Packet {
+    pub version: u8,
+    pub flags: PacketFlags,
+    pub check: Check,
     pub origin: u32,
     pub dest: u32,
+    // Node that last transmitted the packet, which differs from `origin` once
+    // it has been relayed.
+    pub sender: u32,
+    // Node that should handle the packet next when it is routed, or
+    // `BROADCAST` for any node. Older than version 4 this is always
+    // `BROADCAST`.
+    pub next_hop: u32,
     pub packet_id: u32,
-    pub checksum: u16,
+    // Hops the packet may still be relayed. Packets older than version 3
+    // carry neither this nor a sender and are never relayed.
+    pub ttl: u8,
+    pub checksum: u32,
     pub length: u8,
     pub data: Vec<u8>,
 }
 
+// Integrity check carried by a packet. Version 0 packets always use the
+// 16-bit sum, of their addresses, ID and data; other versions sum every header
+// byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Check {
+    Sum,
+    Crc16,
+    Crc32,
+}
+
+impl Check {
+    pub fn from_value(value: u8) -> Option<Check> {
+        match value {
+            0 => Some(Check::Sum),
+            1 => Some(Check::Crc16),
+            2 => Some(Check::Crc32),
+            _ => None,
+        }
+    }
+
+    pub fn value(self) -> u8 {
+        match self {
+            Check::Sum => 0,
+            Check::Crc16 => 1,
+            Check::Crc32 => 2,
+        }
+    }
+
+    // Size of the check value in versioned packets.
+    pub fn size(self) -> usize {
+        match self {
+            Check::Sum | Check::Crc16 => 2,
+            Check::Crc32 => 4,
+        }
+    }
+}
+
+// Header flags. Only carried by version 2 and later packets; older packets
+// decode with every flag cleared.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PacketFlags {
+    pub ack_requested: bool,
+    pub ack: bool,
+    pub fragment: bool,
+    // Set by the application; the payload is carried as is.
+    pub encrypted: bool,
+    pub broadcast: bool,
+    // The data is a routing message rather than application data.
+    pub routing: bool,
+}
+
+impl PacketFlags {
+    pub fn from_bits(bits: u8) -> PacketFlags {
+        PacketFlags {
+            ack_requested: bits & 0x01 != 0,
+            ack: bits & 0x02 != 0,
+            fragment: bits & 0x04 != 0,
+            encrypted: bits & 0x08 != 0,
+            broadcast: bits & 0x10 != 0,
+            routing: bits & 0x20 != 0,
+        }
+    }
+
+    pub fn bits(&self) -> u8 {
+        let mut bits = 0;
+
+        if self.ack_requested {
+            bits |= 0x01;
+        }
+
+        if self.ack {
+            bits |= 0x02;
+        }
+
+        if self.fragment {
+            bits |= 0x04;
+        }
+
+        if self.encrypted {
+            bits |= 0x08;
+        }
+
+        if self.broadcast {
+            bits |= 0x10;
+        }
+
+        if self.routing {
+            bits |= 0x20;
+        }
+
+        bits
+    }
+}
+
 #[derive(Debug, Fail)]
 pub enum PacketError {
     #[fail(display = "Packet read timed out.")]
@@ -20,55 +128,404 @@ pub enum PacketError {
     NotEnoughData,
     #[fail(display = "Checksum does not match packet.")]
     InvalidChecksum,
+    #[fail(display = "Packet does not start with a packet marker.")]
+    InvalidMarker,
+    #[fail(display = "Unsupported packet version {}.", _0)]
+    UnsupportedVersion(u8),
+    #[fail(display = "Unsupported packet check type {}.", _0)]
+    UnsupportedCheck(u8),
+    #[fail(display = "{} bytes is too large to send.", _0)]
+    TooLarge(usize),
+    #[fail(display = "No route to node {:#x}.", _0)]
+    NoRoute(u32),
+    #[fail(display = "Packet {} was not acknowledged.", _0)]
+    DeliveryFailed(u32),
+    #[fail(display = "Packets to node {:#x} cannot be acknowledged.", _0)]
+    InvalidDestination(u32),
+}
+
+// Version 0 packets start with `LEGACY_MARKER` and have no version or check
+// byte. Later versions start with `MARKER` followed by the version.
+const LEGACY_MARKER: [u8; 2] = [0xA3, 0xFF];
+const MARKER: [u8; 2] = [0xA3, 0xFE];
+const VERSION: u8 = 4;
+// Origin, destination, packet ID, checksum and length following the marker.
+const LEGACY_HEADER_LENGTH: usize = 15;
+
+pub const BROADCAST: u32 = 0xFFFF_FFFF;
+pub const MAX_DATA_LENGTH: usize = 255;
+pub const DEFAULT_TTL: u8 = 8;
+// Fragment packets start their data with the fragment index and count.
+const FRAGMENT_HEADER_LENGTH: usize = 2;
+const MAX_FRAGMENTS: usize = 255;
+
+// Size of the header following the marker: version, flags (version 2 on),
+// check type, origin, destination, sender (version 3 on), next hop (version 4
+// on), packet ID, TTL (version 3 on) and length. The check value follows the
+// data.
+fn header_length(version: u8) -> Option<usize> {
+    match version {
+        1 => Some(15),
+        2 => Some(16),
+        3 => Some(21),
+        4 => Some(25),
+        _ => None,
+    }
 }
 
-lazy_static! {
-    static ref PACKET_ID: RwLock<u32> = RwLock::new(0);
+// Packet IDs handed out by one node. IDs go up by one per packet and wrap
+// from 0xFFFFFFFF back to 0, so receivers should compare them with wrapping
+// arithmetic.
+#[derive(Clone, Debug)]
+pub struct PacketSequence {
+    next: u32,
+}
+
+impl Default for PacketSequence {
+    fn default() -> PacketSequence {
+        PacketSequence::new()
+    }
+}
+
+impl PacketSequence {
+    pub fn new() -> PacketSequence {
+        PacketSequence::starting_at(1)
+    }
+
+    pub fn starting_at(id: u32) -> PacketSequence {
+        PacketSequence {
+            next: id,
+        }
+    }
+
+    pub fn next_id(&mut self) -> u32 {
+        self.reserve(1)
+    }
+
+    // Reserves `count` consecutive IDs, returning the first.
+    pub fn reserve(&mut self, count: u32) -> u32 {
+        let first = self.next;
+        self.next = self.next.wrapping_add(count);
+        first
+    }
 }
 
+// Wraps on overflow, as the sum is computed from untrusted bytes and older
+// firmware does the same.
 fn calculate_checksum(origin: u32, dest: u32, id: u32, data: &[u8]) -> u16 {
-    let sum = data.iter().fold(0u32, |sum, &elem| sum + elem as u32);
-    
-    (origin + dest + id + data.len() as u32 + sum) as u16
+    let sum = data.iter().fold(0u32, |sum, &elem| sum.wrapping_add(elem as u32));
+
+    origin.wrapping_add(dest)
+        .wrapping_add(id)
+        .wrapping_add(data.len() as u32)
+        .wrapping_add(sum) as u16
+}
+
+// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 }
+        })
+    })
+}
+
+// CRC-32 as used by Ethernet and zlib.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 }
+        })
+    })
+}
+
+// Total encoded size of the packet at the start of `raw`, marker included.
+// None means more bytes are needed to tell.
+fn encoded_length(raw: &[u8]) -> Result<Option<usize>, Error> {
+    if raw.len() < 2 {
+        return Ok(None);
+    }
+
+    if raw[..2] == LEGACY_MARKER {
+        return Ok(raw.get(16).map(|&length| 2 + LEGACY_HEADER_LENGTH + length as usize));
+    }
+
+    ensure!(raw[..2] == MARKER, PacketError::InvalidMarker);
+
+    let version = match raw.get(2) {
+        Some(&version) => version,
+        None => return Ok(None),
+    };
+
+    let header = header_length(version).ok_or(PacketError::UnsupportedVersion(version))?;
+
+    if raw.len() < 2 + header {
+        return Ok(None);
+    }
+
+    let check = if version >= 2 { raw[4] } else { raw[3] };
+    let check = Check::from_value(check).ok_or(PacketError::UnsupportedCheck(check))?;
+
+    Ok(Some(2 + header + raw[header + 1] as usize + check.size()))
 }
 
 impl Packet {
-    pub fn new(dest: u32, data: &[u8]) -> Self {
-        let mut id = PACKET_ID.write();
-        *id += 1;
+    // Nodes normally take `packet_id` from their own `PacketSequence`; an
+    // explicit origin lets gateways send on behalf of other nodes. The packet
+    // uses the version 0 layout; see `with_check` for the others.
+    pub fn new(origin: u32, dest: u32, packet_id: u32, data: &[u8]) -> Result<Self, Error> {
+        Packet::with_check(origin, dest, packet_id, data, Check::Sum)
+    }
 
-        let checksum = calculate_checksum(0, dest, *id, data);
+    // Packets using `Check::Sum` are sent in the version 0 layout so nodes
+    // running older firmware can still read them.
+    //
+    // Data longer than `MAX_DATA_LENGTH` does not fit in a single packet and
+    // is rejected; use `Packet::fragments` for it instead.
+    pub fn with_check(origin: u32, dest: u32, packet_id: u32, data: &[u8], check: Check)
+        -> Result<Self, Error>
+    {
+        ensure!(data.len() <= MAX_DATA_LENGTH, PacketError::TooLarge(data.len()));
 
-        Packet {
-            origin: 0,
-            dest: dest,
-            packet_id: *id,
-            checksum,
+        let flags = PacketFlags {
+            broadcast: check != Check::Sum && dest == BROADCAST,
+            ..PacketFlags::default()
+        };
+
+        Ok(Packet::build(origin, dest, packet_id, data, check, flags))
+    }
+
+    // Splits `data` into packets that each encode to at most `max_size` bytes,
+    // the radio's maximum payload. Data that fits is sent as a single,
+    // unfragmented packet.
+    pub fn fragments(origin: u32, dest: u32, sequence: &mut PacketSequence, data: &[u8], max_size: usize,
+                     check: Check) -> Result<Vec<Self>, Error>
+    {
+        let overhead = MARKER.len() + header_length(VERSION).unwrap() + check.size();
+
+        if data.len() <= MAX_DATA_LENGTH && overhead + data.len() <= max_size {
+            return Ok(vec![Packet::with_check(origin, dest, sequence.next_id(), data, check)?]);
+        }
+
+        ensure!(max_size > overhead + FRAGMENT_HEADER_LENGTH, PacketError::TooLarge(data.len()));
+
+        let chunk = (max_size - overhead - FRAGMENT_HEADER_LENGTH)
+            .min(MAX_DATA_LENGTH - FRAGMENT_HEADER_LENGTH);
+        let count = data.len().div_ceil(chunk);
+
+        ensure!(count <= MAX_FRAGMENTS, PacketError::TooLarge(data.len()));
+
+        // Fragments use consecutive packet IDs, so the first ID can be worked
+        // out from any fragment and its index.
+        let first = sequence.reserve(count as u32);
+
+        let flags = PacketFlags {
+            fragment: true,
+            broadcast: dest == BROADCAST,
+            ..PacketFlags::default()
+        };
+
+        let packets = data.chunks(chunk).enumerate().map(|(index, chunk)| {
+            let mut fragment = vec![index as u8, count as u8];
+            fragment.extend_from_slice(chunk);
+
+            Packet::build(origin, dest, first.wrapping_add(index as u32), &fragment, check, flags)
+        });
+
+        Ok(packets.collect())
+    }
+
+    fn build(origin: u32, dest: u32, packet_id: u32, data: &[u8], check: Check, flags: PacketFlags) -> Self {
+        let legacy = check == Check::Sum && flags == PacketFlags::default();
+
+        let mut packet = Packet {
+            version: if legacy { 0 } else { VERSION },
+            flags,
+            check,
+            origin,
+            dest,
+            sender: origin,
+            next_hop: BROADCAST,
+            packet_id,
+            ttl: if legacy { 0 } else { DEFAULT_TTL },
+            checksum: 0,
             length: data.len() as u8,
             data: data.into(),
+        };
+
+        packet.checksum = packet.calculate_checksum();
+        packet
+    }
+
+    // Version 0 packets have no room for flags, so setting them moves the
+    // packet to the current layout, keeping its check type.
+    pub fn set_flags(&mut self, flags: PacketFlags) -> &mut Self {
+        if self.version < 2 {
+            self.version = VERSION;
+        }
+
+        self.flags = flags;
+        self.checksum = self.calculate_checksum();
+        self
+    }
+
+    pub fn set_ttl(&mut self, ttl: u8) -> &mut Self {
+        if self.version < 3 {
+            self.version = VERSION;
+        }
+
+        self.ttl = ttl;
+        self.checksum = self.calculate_checksum();
+        self
+    }
+
+    pub fn set_data(&mut self, data: &[u8]) -> Result<&mut Self, Error> {
+        ensure!(data.len() <= MAX_DATA_LENGTH, PacketError::TooLarge(data.len()));
+
+        self.length = data.len() as u8;
+        self.data = data.into();
+        self.checksum = self.calculate_checksum();
+        Ok(self)
+    }
+
+    pub fn set_next_hop(&mut self, next_hop: u32) -> &mut Self {
+        if self.version < 4 {
+            self.version = VERSION;
         }
+
+        self.next_hop = next_hop;
+        self.checksum = self.calculate_checksum();
+        self
+    }
+
+    // Copy of the packet to rebroadcast as `sender`, using up one hop. None
+    // once the packet has no hops left.
+    pub fn relayed(&self, sender: u32) -> Option<Self> {
+        self.forwarded(sender, BROADCAST)
+    }
+
+    // Copy of the packet to pass on to `next_hop` as `sender`, using up one
+    // hop. None once the packet has no hops left.
+    pub fn forwarded(&self, sender: u32, next_hop: u32) -> Option<Self> {
+        if self.ttl == 0 {
+            return None;
+        }
+
+        let mut packet = self.clone();
+        packet.sender = sender;
+        packet.next_hop = next_hop;
+        packet.ttl -= 1;
+
+        if packet.next_hop != BROADCAST && packet.version < 4 {
+            packet.version = VERSION;
+        }
+
+        packet.checksum = packet.calculate_checksum();
+        Some(packet)
     }
 
     pub fn default() -> Self {
         Packet {
+            version: 0,
+            flags: PacketFlags::default(),
+            check: Check::Sum,
             origin: 0,
             dest: 0,
+            sender: 0,
+            next_hop: BROADCAST,
             packet_id: 0,
+            ttl: 0,
             checksum: 0,
             length: 0,
             data: Vec::new(),
         }
     }
 
+    // Decodes a packet of any supported version, starting at its marker. The
+    // counterpart of `as_bytes`.
+    pub fn from_bytes(raw: &[u8]) -> Result<Self, Error> {
+        let length = encoded_length(raw)?.ok_or(PacketError::NotEnoughData)?;
+        ensure!(raw.len() >= length, PacketError::NotEnoughData);
+
+        if raw[..2] == LEGACY_MARKER {
+            return Packet::from_legacy_data(&raw[2..]);
+        }
+
+        // The length checks above make the reads below infallible.
+        let mut cursor = Cursor::new(&raw[2..length]);
+        let version = cursor.read_u8()?;
+
+        let flags = if version >= 2 {
+            PacketFlags::from_bits(cursor.read_u8()?)
+        } else {
+            PacketFlags::default()
+        };
+
+        let check = cursor.read_u8()?;
+        let check = Check::from_value(check).ok_or(PacketError::UnsupportedCheck(check))?;
+        let origin = cursor.read_u32::<LittleEndian>()?;
+        let dest = cursor.read_u32::<LittleEndian>()?;
+        let sender = if version >= 3 { cursor.read_u32::<LittleEndian>()? } else { origin };
+        let next_hop = if version >= 4 { cursor.read_u32::<LittleEndian>()? } else { BROADCAST };
+        let packet_id = cursor.read_u32::<LittleEndian>()?;
+        let ttl = if version >= 3 { cursor.read_u8()? } else { 0 };
+        let data_length = cursor.read_u8()?;
+
+        let start = 2 + cursor.position() as usize;
+        let end = length - check.size();
+
+        let packet = Packet {
+            version,
+            flags,
+            check,
+            origin,
+            dest,
+            sender,
+            next_hop,
+            packet_id,
+            ttl,
+            checksum: match check {
+                Check::Crc32 => LittleEndian::read_u32(&raw[end..]),
+                _ => LittleEndian::read_u16(&raw[end..]) as u32,
+            },
+            length: data_length,
+            data: raw[start..end].into(),
+        };
+
+        ensure!(packet.is_valid(), PacketError::InvalidChecksum);
+
+        Ok(packet)
+    }
+
+    // Decodes a packet of any version, picking the layout and check from the
+    // version its marker names. Input without a marker is taken to be the
+    // bytes following a 0xA3 0xFF marker, as earlier releases expected, and
+    // decoded as version 0.
     pub fn from_data(raw: &[u8]) -> Result<Self, Error> {
-        ensure!(raw.len() > 13, PacketError::NotEnoughData);
-        ensure!(raw.len() >= raw[12] as usize + 13, PacketError::NotEnoughData);
+        if !raw.starts_with(&MARKER) && !raw.starts_with(&LEGACY_MARKER) {
+            return Packet::from_legacy_data(raw);
+        }
+
+        // A version 0 origin can start with the same two bytes as a marker.
+        Packet::from_bytes(raw).or_else(|err| Packet::from_legacy_data(raw).map_err(|_| err))
+    }
+
+    // Decodes a version 0 packet from the bytes following its marker.
+    fn from_legacy_data(raw: &[u8]) -> Result<Self, Error> {
+        ensure!(raw.len() >= LEGACY_HEADER_LENGTH, PacketError::NotEnoughData);
+        ensure!(raw.len() >= LEGACY_HEADER_LENGTH + raw[14] as usize, PacketError::NotEnoughData);
 
         let packet = Packet {
-            origin: LittleEndian::read_u32(&raw),
+            version: 0,
+            flags: PacketFlags::default(),
+            check: Check::Sum,
+            origin: LittleEndian::read_u32(raw),
             dest: LittleEndian::read_u32(&raw[4..]),
+            sender: LittleEndian::read_u32(raw),
+            next_hop: BROADCAST,
             packet_id: LittleEndian::read_u32(&raw[8..]),
-            checksum: LittleEndian::read_u16(&raw[12..]),
+            ttl: 0,
+            checksum: LittleEndian::read_u16(&raw[12..]) as u32,
             length: raw[14],
             data: if raw[14] > 0 { 
                     raw[15..15 + raw[14] as usize].into() 
@@ -82,26 +539,326 @@ impl Packet {
         Ok(packet)
     }
 
-    pub fn as_bytes(&self) -> Vec<u8> {
+    // Everything the check of a versioned packet covers: version through data.
+    fn header_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
 
-        bytes.write_u8(0xA3).unwrap();
-        bytes.write_u8(0xFF).unwrap();
+        bytes.write_u8(self.version).unwrap();
+
+        if self.version >= 2 {
+            bytes.write_u8(self.flags.bits()).unwrap();
+        }
+
+        bytes.write_u8(self.check.value()).unwrap();
         bytes.write_u32::<LittleEndian>(self.origin).unwrap();
         bytes.write_u32::<LittleEndian>(self.dest).unwrap();
+
+        if self.version >= 3 {
+            bytes.write_u32::<LittleEndian>(self.sender).unwrap();
+        }
+
+        if self.version >= 4 {
+            bytes.write_u32::<LittleEndian>(self.next_hop).unwrap();
+        }
+
         bytes.write_u32::<LittleEndian>(self.packet_id).unwrap();
-        bytes.write_u16::<LittleEndian>(self.checksum).unwrap();
+
+        if self.version >= 3 {
+            bytes.write_u8(self.ttl).unwrap();
+        }
+
         bytes.write_u8(self.length).unwrap();
         bytes.extend_from_slice(&self.data);
 
         bytes
     }
 
+    // The version 0 sum only covers the fields that layout has. Newer layouts
+    // sum every header byte instead, so flags and routing fields are covered
+    // too.
+    fn calculate_checksum(&self) -> u32 {
+        match self.check {
+            Check::Sum if self.version == 0 => {
+                calculate_checksum(self.origin, self.dest, self.packet_id, &self.data) as u32
+            }
+            Check::Sum => {
+                self.header_bytes().iter().fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16)) as u32
+            }
+            Check::Crc16 => crc16(&self.header_bytes()) as u32,
+            Check::Crc32 => crc32(&self.header_bytes()),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        if self.version == 0 {
+            bytes.extend_from_slice(&LEGACY_MARKER);
+            bytes.write_u32::<LittleEndian>(self.origin).unwrap();
+            bytes.write_u32::<LittleEndian>(self.dest).unwrap();
+            bytes.write_u32::<LittleEndian>(self.packet_id).unwrap();
+            bytes.write_u16::<LittleEndian>(self.checksum as u16).unwrap();
+            bytes.write_u8(self.length).unwrap();
+            bytes.extend_from_slice(&self.data);
+
+            return bytes;
+        }
+
+        bytes.extend_from_slice(&MARKER);
+        bytes.extend_from_slice(&self.header_bytes());
+
+        match self.check {
+            Check::Crc32 => bytes.write_u32::<LittleEndian>(self.checksum).unwrap(),
+            _ => bytes.write_u16::<LittleEndian>(self.checksum as u16).unwrap(),
+        }
+
+        bytes
+    }
+
     pub fn data(&self) -> &Vec<u8> {
         &self.data
     }
 
+    // Acknowledgement of this packet, sent back by its destination with its
+    // own `packet_id` and the same check type.
+    pub fn ack(&self, packet_id: u32) -> Packet {
+        let mut data = Vec::new();
+        data.write_u32::<LittleEndian>(self.packet_id).unwrap();
+
+        let flags = PacketFlags {
+            ack: true,
+            ..PacketFlags::default()
+        };
+
+        Packet::build(self.dest, self.origin, packet_id, &data, self.check, flags)
+    }
+
+    // ID of the packet an acknowledgement is for.
+    pub fn acked_id(&self) -> Option<u32> {
+        if !self.flags.ack || self.data.len() < 4 {
+            return None;
+        }
+
+        Some(LittleEndian::read_u32(&self.data))
+    }
+
+    // Index, fragment count and data of a fragment packet.
+    pub fn fragment(&self) -> Option<(u8, u8, &[u8])> {
+        if !self.flags.fragment || self.data.len() < FRAGMENT_HEADER_LENGTH {
+            return None;
+        }
+
+        Some((self.data[0], self.data[1], &self.data[FRAGMENT_HEADER_LENGTH..]))
+    }
+
     pub fn is_valid(&self) -> bool {
-        self.checksum == calculate_checksum(self.origin, self.dest, self.packet_id, &self.data)
+        self.checksum == self.calculate_checksum()
+    }
+}
+
+// Splits a byte stream into packets. Bytes can be pushed in chunks of any
+// size; anything left over after a packet is kept for the next one.
+pub struct PacketDecoder {
+    buffer: Vec<u8>,
+}
+
+impl Default for PacketDecoder {
+    fn default() -> PacketDecoder {
+        PacketDecoder::new()
     }
-}
\ No newline at end of file
+}
+
+impl PacketDecoder {
+    pub fn new() -> PacketDecoder {
+        PacketDecoder {
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    pub fn next_packet(&mut self) -> Option<Result<Packet, Error>> {
+        let start = self.buffer.windows(2).position(|window| {
+            window == LEGACY_MARKER || window == MARKER
+        });
+
+        match start {
+            Some(start) => {
+                self.buffer.drain(..start);
+            }
+            None => {
+                // Keep a trailing 0xA3 in case the rest of the marker is still
+                // to come.
+                let keep = if self.buffer.last() == Some(&MARKER[0]) { 1 } else { 0 };
+                let end = self.buffer.len() - keep;
+                self.buffer.drain(..end);
+                return None;
+            }
+        }
+
+        let result = match encoded_length(&self.buffer) {
+            Ok(Some(length)) if self.buffer.len() >= length => {
+                Packet::from_bytes(&self.buffer[..length]).map(|packet| (packet, length))
+            }
+            Ok(_) => return None,
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok((packet, length)) => {
+                self.buffer.drain(..length);
+                Some(Ok(packet))
+            }
+            Err(err) => {
+                // Skip the marker only, in case a real packet starts inside
+                // what looked like this one.
+                self.buffer.drain(..2);
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECK_INPUT: &[u8] = b"123456789";
+
+    #[test]
+    fn crc16_known_answer() {
+        assert_eq!(crc16(CHECK_INPUT), 0x29B1);
+    }
+
+    #[test]
+    fn crc32_known_answer() {
+        assert_eq!(crc32(CHECK_INPUT), 0xCBF4_3926);
+    }
+
+    fn decode_all(decoder: &mut PacketDecoder) -> Vec<Result<Packet, Error>> {
+        let mut results = Vec::new();
+
+        while let Some(result) = decoder.next_packet() {
+            results.push(result);
+        }
+
+        results
+    }
+
+    #[test]
+    fn decoder_reads_packets_pushed_in_chunks() {
+        let legacy = Packet::new(1, 2, 3, b"legacy").unwrap();
+        let versioned = Packet::with_check(1, 2, 4, b"versioned", Check::Crc32).unwrap();
+
+        let mut stream = legacy.as_bytes();
+        stream.extend(versioned.as_bytes());
+
+        let mut decoder = PacketDecoder::new();
+        let mut packets = Vec::new();
+
+        for chunk in stream.chunks(3) {
+            decoder.push(chunk);
+            packets.extend(decode_all(&mut decoder).into_iter().map(Result::unwrap));
+        }
+
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].version, 0);
+        assert_eq!(packets[0].packet_id, 3);
+        assert_eq!(packets[0].data(), b"legacy");
+        assert_eq!(packets[1].version, VERSION);
+        assert_eq!(packets[1].check, Check::Crc32);
+        assert_eq!(packets[1].data(), b"versioned");
+    }
+
+    #[test]
+    fn decoder_resyncs_after_garbage_and_bad_checksums() {
+        let good = Packet::with_check(1, 2, 5, b"good", Check::Crc16).unwrap();
+        let mut bad = Packet::with_check(1, 2, 6, b"bad", Check::Crc16).unwrap().as_bytes();
+        let last = bad.len() - 1;
+        bad[last] ^= 0xFF;
+
+        let mut decoder = PacketDecoder::new();
+        decoder.push(&[0x00, 0xA3, 0x12, 0xA3]);
+        decoder.push(&bad);
+        decoder.push(&good.as_bytes());
+
+        let results = decode_all(&mut decoder);
+
+        assert!(results[..results.len() - 1].iter().all(Result::is_err));
+        assert_eq!(results.last().unwrap().as_ref().unwrap().packet_id, 5);
+        assert!(decoder.next_packet().is_none());
+    }
+
+    #[test]
+    fn decoder_handles_addresses_near_the_top_of_the_range() {
+        let packet = Packet::new(0xFFFF_FFF0, 0xFFFF_FFFE, 0xFFFF_FFFF, b"high").unwrap();
+
+        let mut decoder = PacketDecoder::new();
+        // A legacy header of 0xFF bytes with no data.
+        decoder.push(&[0xA3, 0xFF]);
+        decoder.push(&[0xFF; 14]);
+        decoder.push(&[0x00]);
+        decoder.push(&packet.as_bytes());
+
+        let results = decode_all(&mut decoder);
+        let decoded = results.last().unwrap().as_ref().unwrap();
+
+        assert_eq!(decoded.origin, 0xFFFF_FFF0);
+        assert_eq!(decoded.dest, 0xFFFF_FFFE);
+        assert_eq!(decoded.data(), b"high");
+    }
+
+    #[test]
+    fn broadcast_packets_round_trip() {
+        let legacy = Packet::new(1, BROADCAST, 3, b"x").unwrap();
+        let decoded = Packet::from_bytes(&legacy.as_bytes()).unwrap();
+        assert_eq!(decoded.dest, BROADCAST);
+        assert_eq!(decoded.version, 0);
+
+        let mut relayed = legacy.clone();
+        relayed.set_ttl(DEFAULT_TTL);
+        let relayed = relayed.relayed(2).unwrap();
+        let decoded = Packet::from_bytes(&relayed.as_bytes()).unwrap();
+        assert_eq!(decoded.dest, BROADCAST);
+        assert_eq!(decoded.sender, 2);
+    }
+
+    #[test]
+    fn sum_covers_the_versioned_header() {
+        let mut packet = Packet::new(1, 2, 3, b"data").unwrap();
+        packet.set_ttl(DEFAULT_TTL);
+
+        let mut bytes = packet.as_bytes();
+        // Flip the fragment flag.
+        bytes[3] ^= 0x04;
+
+        assert!(Packet::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn from_data_picks_the_layout_by_version() {
+        let legacy = Packet::new(1, 2, 3, b"legacy").unwrap().as_bytes();
+        let versioned = Packet::with_check(1, 2, 4, b"versioned", Check::Crc32).unwrap().as_bytes();
+
+        assert_eq!(Packet::from_data(&legacy[2..]).unwrap().packet_id, 3);
+        assert_eq!(Packet::from_data(&legacy).unwrap().packet_id, 3);
+
+        let decoded = Packet::from_data(&versioned).unwrap();
+        assert_eq!(decoded.version, VERSION);
+        assert_eq!(decoded.check, Check::Crc32);
+    }
+
+    #[test]
+    fn rejects_data_longer_than_a_packet() {
+        let data = [0; MAX_DATA_LENGTH + 1];
+
+        assert!(Packet::new(1, 2, 3, &data).is_err());
+        assert!(Packet::new(1, 2, 3, &data[1..]).unwrap().set_data(&data).is_err());
+    }
+}
@@ -0,0 +1,29 @@
+use std::time::Duration;
+
+// How `Xbee::send_reliable` retransmits packets that are not acknowledged.
+// The wait for an acknowledgement starts at `timeout` and is multiplied by
+// `backoff` after every attempt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub timeout: Duration,
+    pub retries: u32,
+    pub backoff: u32,
+}
+
+impl RetryPolicy {
+    pub fn new(timeout: Duration, retries: u32, backoff: u32) -> RetryPolicy {
+        RetryPolicy {
+            timeout,
+            retries,
+            backoff,
+        }
+    }
+
+    // Time to wait for acknowledgements after attempt `attempt`, counting
+    // from 0.
+    pub fn timeout(&self, attempt: u32) -> Duration {
+        self.timeout
+            .checked_mul(self.backoff.saturating_pow(attempt))
+            .unwrap_or_else(|| Duration::from_secs(u32::MAX as u64))
+    }
+}
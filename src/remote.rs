@@ -0,0 +1,85 @@
+use failure::Error;
+
+use api::*;
+use {Xbee, XbeeConfig};
+
+// Handle for configuring another radio over the air with Remote AT Command
+// frames. Requires the local radio to be in API mode.
+pub struct RemoteXbee<'a> {
+    xbee: &'a mut Xbee,
+    dest64: u64,
+    dest16: u16,
+}
+
+impl<'a> RemoteXbee<'a> {
+    pub fn new(xbee: &'a mut Xbee, dest64: u64, dest16: u16) -> RemoteXbee<'a> {
+        RemoteXbee {
+            xbee,
+            dest64,
+            dest16,
+        }
+    }
+
+    fn send(&mut self, cmd: &str, parameter: &[u8], apply_changes: bool) -> Result<Vec<u8>, Error> {
+        ensure!(cmd.len() == 2, CommandError::InvalidCommand(cmd.into()));
+
+        let (dest64, dest16) = (self.dest64, self.dest16);
+
+        let response = self.xbee.request(|frame_id| ApiFrame::RemoteAtCommand {
+            frame_id,
+            dest64,
+            dest16,
+            apply_changes,
+            command: cmd.into(),
+            parameter: parameter.into(),
+        })?;
+
+        match response {
+            ApiFrame::RemoteAtCommandResponse { status, data, .. } => {
+                status.check(cmd)?;
+                Ok(data)
+            }
+            frame => Err(ApiError::UnexpectedFrame(frame.frame_type()).into()),
+        }
+    }
+
+    pub fn at_command(&mut self, cmd: &str, parameter: &[u8]) -> Result<Vec<u8>, Error> {
+        self.send(cmd, parameter, true)
+    }
+
+    pub fn register(&mut self, cmd: &str) -> Result<u64, Error> {
+        Ok(parameter_value(&self.send(cmd, &[], true)?))
+    }
+
+    pub fn set_register(&mut self, cmd: &str, value: u64) -> Result<(), Error> {
+        self.send(cmd, &parameter_bytes(value), true)?;
+        Ok(())
+    }
+
+    // Sets a register without applying it until a later AC or WR.
+    pub fn queue_register(&mut self, cmd: &str, value: u64) -> Result<(), Error> {
+        self.send(cmd, &parameter_bytes(value), false)?;
+        Ok(())
+    }
+
+    pub fn execute(&mut self, cmd: &str) -> Result<(), Error> {
+        self.send(cmd, &[], true)?;
+        Ok(())
+    }
+
+    pub fn edit_config<F>(&mut self, edit: F) -> Result<(), Error>
+        where F: FnOnce(&mut XbeeConfig)
+    {
+        let mut config = XbeeConfig::new();
+
+        edit(&mut config);
+
+        for (cmd, value) in config.commands()? {
+            self.queue_register(cmd, value)?;
+        }
+
+        self.execute("WR")?;
+        self.execute("AC")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use failure::Error;
+
+use std::collections::HashMap;
+use std::collections::hash_map::Iter;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Fail)]
+pub enum RoutingError {
+    #[fail(display = "Not enough data to form routing message.")]
+    NotEnoughData,
+    #[fail(display = "Unknown routing message type {}.", _0)]
+    UnknownMessage(u8),
+}
+
+// Control messages of the on-demand routing layer, carried as the data of
+// packets with the routing flag set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteMessage {
+    // Flooded by the packet's origin to find a route to `target`.
+    Request { target: u32, hops: u8 },
+    // Sent back along the reverse route by `target` once a request reaches
+    // it. The packet's origin is the target.
+    Reply { target: u32, hops: u8 },
+    // Sent towards the packet's destination when a node on the way has no
+    // route to `unreachable`.
+    Error { unreachable: u32 },
+}
+
+impl RouteMessage {
+    pub fn from_bytes(data: &[u8]) -> Result<RouteMessage, Error> {
+        ensure!(data.len() >= 5, RoutingError::NotEnoughData);
+
+        let address = LittleEndian::read_u32(&data[1..]);
+
+        let message = match data[0] {
+            0x01 | 0x02 => {
+                ensure!(data.len() >= 6, RoutingError::NotEnoughData);
+
+                if data[0] == 0x01 {
+                    RouteMessage::Request { target: address, hops: data[5] }
+                } else {
+                    RouteMessage::Reply { target: address, hops: data[5] }
+                }
+            }
+            0x03 => RouteMessage::Error { unreachable: address },
+            kind => return Err(RoutingError::UnknownMessage(kind).into()),
+        };
+
+        Ok(message)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        match *self {
+            RouteMessage::Request { target, hops } => {
+                bytes.write_u8(0x01).unwrap();
+                bytes.write_u32::<LittleEndian>(target).unwrap();
+                bytes.write_u8(hops).unwrap();
+            }
+            RouteMessage::Reply { target, hops } => {
+                bytes.write_u8(0x02).unwrap();
+                bytes.write_u32::<LittleEndian>(target).unwrap();
+                bytes.write_u8(hops).unwrap();
+            }
+            RouteMessage::Error { unreachable } => {
+                bytes.write_u8(0x03).unwrap();
+                bytes.write_u32::<LittleEndian>(unreachable).unwrap();
+            }
+        }
+
+        bytes
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Route {
+    pub next_hop: u32,
+    pub hops: u8,
+    pub expires: Instant,
+}
+
+// Next hop towards each known node. Routes are refreshed whenever they are
+// learned again or shown to work, and dropped once `lifetime` passes without
+// that.
+pub struct RouteTable {
+    routes: HashMap<u32, Route>,
+    lifetime: Duration,
+}
+
+impl RouteTable {
+    pub fn new(lifetime: Duration) -> RouteTable {
+        RouteTable {
+            routes: HashMap::new(),
+            lifetime,
+        }
+    }
+
+    // Records a route to `dest` unless a shorter one is already known.
+    pub fn update(&mut self, dest: u32, next_hop: u32, hops: u8) {
+        if let Some(route) = self.route(dest) {
+            if route.hops < hops {
+                return;
+            }
+        }
+
+        let expires = Instant::now() + self.lifetime;
+        self.routes.insert(dest, Route { next_hop, hops, expires });
+    }
+
+    pub fn set_lifetime(&mut self, lifetime: Duration) {
+        self.lifetime = lifetime;
+    }
+
+    pub fn route(&self, dest: u32) -> Option<&Route> {
+        self.routes.get(&dest).filter(|route| route.expires > Instant::now())
+    }
+
+    // Extends the lifetime of the route to `dest` after it was shown to work,
+    // such as by an acknowledgement from `dest`.
+    pub fn refresh(&mut self, dest: u32) {
+        let expires = Instant::now() + self.lifetime;
+
+        if let Some(route) = self.routes.get_mut(&dest) {
+            route.expires = route.expires.max(expires);
+        }
+    }
+
+    // Extends the lifetime of every route through `next_hop` after traffic was
+    // heard from it.
+    pub fn refresh_via(&mut self, next_hop: u32) {
+        let expires = Instant::now() + self.lifetime;
+
+        for route in self.routes.values_mut().filter(|route| route.next_hop == next_hop) {
+            route.expires = route.expires.max(expires);
+        }
+    }
+
+    pub fn remove(&mut self, dest: u32) -> Option<Route> {
+        self.routes.remove(&dest)
+    }
+
+    pub fn routes(&self) -> Iter<'_, u32, Route> {
+        self.routes.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.routes.clear();
+    }
+
+    pub fn expire(&mut self) {
+        let now = Instant::now();
+        self.routes.retain(|_, route| route.expires > now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_messages_round_trip() {
+        let messages = [
+            RouteMessage::Request { target: 0x1234_5678, hops: 2 },
+            RouteMessage::Reply { target: 7, hops: 0 },
+            RouteMessage::Error { unreachable: 9 },
+        ];
+
+        for &message in &messages {
+            assert_eq!(RouteMessage::from_bytes(&message.as_bytes()).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn rejects_short_and_unknown_messages() {
+        let request = RouteMessage::Request { target: 1, hops: 1 }.as_bytes();
+
+        assert!(RouteMessage::from_bytes(&request[..5]).is_err());
+        assert!(RouteMessage::from_bytes(&[0x04, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn keeps_the_shortest_route() {
+        let mut table = RouteTable::new(Duration::from_secs(60));
+        table.update(5, 2, 3);
+        table.update(5, 3, 4);
+        assert_eq!(table.route(5).unwrap().next_hop, 2);
+
+        table.update(5, 4, 1);
+        assert_eq!(table.route(5).unwrap().next_hop, 4);
+    }
+
+    #[test]
+    fn refreshes_routes_through_a_neighbour() {
+        let mut table = RouteTable::new(Duration::from_millis(50));
+        table.update(5, 2, 2);
+        table.update(6, 3, 2);
+
+        table.set_lifetime(Duration::from_secs(60));
+        table.refresh_via(2);
+        std::thread::sleep(Duration::from_millis(60));
+
+        assert!(table.route(5).is_some());
+        assert!(table.route(6).is_none());
+    }
+
+    #[test]
+    fn expired_routes_are_not_used() {
+        let mut table = RouteTable::new(Duration::from_secs(0));
+        table.update(5, 2, 1);
+
+        assert!(table.route(5).is_none());
+
+        table.expire();
+        assert!(table.is_empty());
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Iter;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceRoute {
+    pub dest16: u16,
+    // Intermediate hops as reported by the route record, closest to the
+    // destination first, which is also the order Create Source Route expects.
+    pub hops: Vec<u16>,
+    pub updated: Instant,
+}
+
+// Routes to remote radios learned from Route Record Indicator frames, so the
+// coordinator can send Create Source Route frames instead of relying on the
+// radio's own route table.
+pub struct SourceRouteCache {
+    routes: HashMap<u64, SourceRoute>,
+    max_age: Duration,
+}
+
+impl SourceRouteCache {
+    pub fn new(max_age: Duration) -> SourceRouteCache {
+        SourceRouteCache {
+            routes: HashMap::new(),
+            max_age,
+        }
+    }
+
+    pub fn record(&mut self, dest64: u64, dest16: u16, hops: Vec<u16>) {
+        self.routes.insert(dest64, SourceRoute {
+            dest16,
+            hops,
+            updated: Instant::now(),
+        });
+    }
+
+    pub fn route(&self, dest64: u64) -> Option<&SourceRoute> {
+        self.routes.get(&dest64)
+            .filter(|route| route.updated.elapsed() < self.max_age)
+    }
+
+    pub fn remove(&mut self, dest64: u64) -> Option<SourceRoute> {
+        self.routes.remove(&dest64)
+    }
+
+    pub fn expire(&mut self) {
+        let max_age = self.max_age;
+        self.routes.retain(|_, route| route.updated.elapsed() < max_age);
+    }
+
+    pub fn routes(&self) -> Iter<'_, u64, SourceRoute> {
+        self.routes.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}